@@ -1,4 +1,3 @@
-use anyhow::{Context, Result};
 use tracing::info;
 use wp_mini::WattpadClient;
 use crate::error::AppError;
@@ -12,18 +11,16 @@ pub async fn login(
     wp_client
         .authenticate(username, password)
         .await
-        .context("Wattpad login request failed")
-        .map_err(|_e| AppError::AuthenticationFailed)?;
+        .map_err(|source| AppError::AuthenticationFailed { source })?;
 
     Ok(())
 }
 
-pub async fn logout(wp_client: &WattpadClient) -> Result<()> {
+pub async fn logout(wp_client: &WattpadClient) -> Result<(), AppError> {
     info!("Attempting to logout via core::auth");
     wp_client
         .deauthenticate()
         .await
-        .context("Wattpad logout request failed")
-        .map_err(|_| AppError::LogoutFailed)?;
+        .map_err(|source| AppError::LogoutFailed { source })?;
     Ok(())
 }
@@ -0,0 +1,248 @@
+use crate::error::AppError;
+use crate::options::DownloadOptions;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use crate::processor::download_story_to_folder;
+use crate::processor::download_story_to_memory;
+use crate::rate_limit::RateLimiter;
+use crate::types::StoryDownload;
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tracing::info;
+use wp_mini::WattpadClient;
+
+/// How many bytes of images a batch keeps in memory to share between its stories.
+const SHARED_IMAGE_BUDGET: usize = 64 * 1024 * 1024;
+
+/// The outcome of one story of a batch download.
+pub struct BatchItem<T> {
+    /// The Wattpad story ID.
+    pub story_id: u64,
+    /// The download result, exactly as the single-story function would have returned it.
+    pub result: Result<StoryDownload<T>, AppError>,
+}
+
+/// Aggregated outcome of a batch download, see [`StoryBatch::summary`].
+#[derive(Debug, Clone, Default)]
+pub struct BatchSummary {
+    /// The number of stories in the batch.
+    pub total: usize,
+    /// Stories whose EPUB is complete.
+    pub succeeded: Vec<u64>,
+    /// Stories whose EPUB was written, but with missing chapters, images or cover.
+    /// See [`crate::DownloadReport::is_complete`].
+    pub incomplete: Vec<u64>,
+    /// Stories that failed, with the error message.
+    pub failed: Vec<(u64, String)>,
+}
+
+impl BatchSummary {
+    /// The number of stories that have finished, successfully or not.
+    pub fn finished(&self) -> usize {
+        self.succeeded.len() + self.incomplete.len() + self.failed.len()
+    }
+
+    /// Returns `true` when every story finished with a complete EPUB.
+    pub fn is_complete(&self) -> bool {
+        self.succeeded.len() == self.total
+    }
+
+    fn record<T>(&mut self, item: &BatchItem<T>) {
+        match &item.result {
+            Ok(download) if download.report.is_complete() => self.succeeded.push(item.story_id),
+            Ok(_) => self.incomplete.push(item.story_id),
+            Err(e) => self.failed.push((item.story_id, e.to_string())),
+        }
+    }
+}
+
+/// A running batch download, yielding a [`BatchItem`] whenever a story finishes.
+///
+/// Stories finish in any order. Dropping the batch stops the remaining downloads.
+pub struct StoryBatch<'a, T> {
+    items: Pin<Box<dyn Stream<Item = BatchItem<T>> + 'a>>,
+    summary: BatchSummary,
+}
+
+impl<T> StoryBatch<'_, T> {
+    /// The outcome of the stories finished so far.
+    pub fn summary(&self) -> &BatchSummary {
+        &self.summary
+    }
+
+    /// Waits for the remaining stories, discarding their results, and returns the summary.
+    pub async fn finish(mut self) -> BatchSummary {
+        while self.next().await.is_some() {}
+        self.summary
+    }
+}
+
+impl<T> Stream for StoryBatch<'_, T> {
+    type Item = BatchItem<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.items.as_mut().poll_next(cx);
+        if let Poll::Ready(Some(item)) = &poll {
+            self.summary.record(item);
+        }
+        poll
+    }
+}
+
+/// Downloads several stories into `output_path`, see [`download_stories_to_memory`]
+/// for how the stories share their resources.
+///
+/// Excluded for wasm32
+///
+/// # Arguments
+/// * `story_ids` - The stories to download. Duplicates are downloaded once.
+/// * `options` - The download settings, applied to every story.
+/// * `output_path` - The directory where the `.epub` files will be saved.
+///
+/// # Returns
+/// A [`StoryBatch`] yielding the `PathBuf`s of every story's generated files.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn download_stories_to_folder<'a>(
+    wattpad_client: &'a WattpadClient,
+    reqwest_client: &'a Client,
+    story_ids: impl IntoIterator<Item = u64>,
+    options: &DownloadOptions,
+    output_path: &'a Path,
+) -> StoryBatch<'a, Vec<PathBuf>> {
+    run_batch(story_ids, options, move |story_id, options| async move {
+        download_story_to_folder(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            &options,
+            output_path,
+        )
+        .await
+    })
+}
+
+/// Downloads several stories, yielding every EPUB as bytes as soon as it is done.
+///
+/// The stories share their resources:
+/// - [`crate::DownloadOptionsBuilder::concurrent_stories`] stories are downloaded at once.
+/// - Every request of the batch goes through one [`RateLimiter`]. Unless `options`
+///   already has one, the batch creates a limiter allowing
+///   [`crate::DownloadOptionsBuilder::concurrent_images`] requests in flight in total.
+/// - Images already downloaded by a story of the batch are reused by the others, so images
+///   shared by several stories (e.g. banners of the same author) are usually fetched once.
+///   Stories requesting the same image at the same moment may still both download it.
+///   Together with a [`crate::DownloadCache`], images are shared across batches as well.
+/// - The retry policy and cancellation token of `options` apply to every story, and
+///   connections are reused through `reqwest_client`.
+/// - The progress callback of `options` receives the events of every story, wrapped in
+///   [`crate::ProgressEvent::Story`] to tell them apart.
+///
+/// # Arguments
+/// * `story_ids` - The stories to download. Duplicates are downloaded once.
+/// * `options` - The download settings, applied to every story.
+///
+/// # Returns
+/// A [`StoryBatch`] yielding the `Vec<u8>` of every generated EPUB.
+pub fn download_stories_to_memory<'a>(
+    wattpad_client: &'a WattpadClient,
+    reqwest_client: &'a Client,
+    story_ids: impl IntoIterator<Item = u64>,
+    options: &DownloadOptions,
+) -> StoryBatch<'a, Vec<u8>> {
+    run_batch(story_ids, options, move |story_id, options| async move {
+        download_story_to_memory(wattpad_client, reqwest_client, story_id, &options).await
+    })
+}
+
+fn run_batch<'a, T, F, Fut>(
+    story_ids: impl IntoIterator<Item = u64>,
+    options: &DownloadOptions,
+    download: F,
+) -> StoryBatch<'a, T>
+where
+    T: 'a,
+    F: Fn(u64, Arc<DownloadOptions>) -> Fut + 'a,
+    Fut: Future<Output = Result<StoryDownload<T>, AppError>> + 'a,
+{
+    let mut story_ids: Vec<u64> = story_ids.into_iter().collect();
+    let mut seen = HashSet::new();
+    story_ids.retain(|story_id| seen.insert(*story_id));
+    info!(count = story_ids.len(), "Starting batch download");
+
+    let mut batch_options = options.clone();
+    batch_options.rate_limiter.get_or_insert_with(|| {
+        RateLimiter::builder()
+            .max_concurrent_requests(options.concurrent_images)
+            .build()
+    });
+    batch_options.shared_images = Some(SharedImages::default());
+
+    let summary = BatchSummary {
+        total: story_ids.len(),
+        ..Default::default()
+    };
+    let items = stream::iter(story_ids)
+        .map(move |story_id| {
+            let mut story_options = batch_options.clone();
+            story_options.progress = batch_options.progress.for_story(story_id);
+            let result = download(story_id, Arc::new(story_options));
+            async move {
+                BatchItem {
+                    story_id,
+                    result: result.await,
+                }
+            }
+        })
+        .buffer_unordered(options.concurrent_stories);
+
+    StoryBatch {
+        items: Box::pin(items),
+        summary,
+    }
+}
+
+/// Images downloaded during a batch, shared between its stories up to a memory budget.
+#[derive(Clone, Default)]
+pub(crate) struct SharedImages {
+    inner: Arc<Mutex<SharedImagesInner>>,
+}
+
+#[derive(Default)]
+struct SharedImagesInner {
+    images: HashMap<String, Vec<u8>>,
+    bytes: usize,
+}
+
+impl SharedImages {
+    pub(crate) fn get(&self, url: &str) -> Option<Vec<u8>> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.images.get(url).cloned()
+    }
+
+    /// Keeps an image, unless that would exceed the budget.
+    pub(crate) fn insert(&self, url: &str, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.bytes + data.len() > SHARED_IMAGE_BUDGET || inner.images.contains_key(url) {
+            return;
+        }
+        inner.bytes += data.len();
+        inner.images.insert(url.to_string(), data.to_vec());
+    }
+}
+
+impl fmt::Debug for SharedImages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        f.debug_struct("SharedImages")
+            .field("images", &inner.images.len())
+            .field("bytes", &inner.bytes)
+            .finish()
+    }
+}
@@ -0,0 +1,252 @@
+use crate::models::ImageValidators;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+use tracing::warn;
+use wp_mini::types::PartStubResponse;
+
+/// A persistent on-disk cache for chapter content and images.
+///
+/// Excluded for wasm32
+///
+/// - Chapter HTML is stored per story and part ID and stays valid for as long as
+///   the part's `modify_date` in the story metadata is unchanged. When every part
+///   of a story is cached, the content ZIP is not downloaded at all.
+/// - Images (and the cover) are stored per URL together with their `ETag` and
+///   `Last-Modified` headers. After [`DownloadCache::revalidate_after`] they are
+///   revalidated with a conditional request instead of being downloaded again.
+/// - The story metadata is always fetched, since it decides whether cached
+///   chapters are still current.
+///
+/// Entries older than [`DownloadCache::max_age`] are ignored and replaced.
+#[derive(Debug, Clone)]
+pub struct DownloadCache {
+    dir: PathBuf,
+    max_age: Duration,
+    revalidate_after: Duration,
+}
+
+/// A cached image.
+pub(crate) struct CachedImage {
+    pub(crate) data: Vec<u8>,
+    pub(crate) validators: ImageValidators,
+    /// Whether the image may be used without asking the server first.
+    pub(crate) fresh: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PartEntry {
+    modify_date: String,
+    stored_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ImageEntry {
+    url: String,
+    #[serde(flatten)]
+    validators: ImageValidators,
+    stored_at: u64,
+}
+
+impl DownloadCache {
+    /// Creates a cache stored in `dir`, which is created when first written to.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_age: Duration::from_secs(30 * 24 * 60 * 60),
+            revalidate_after: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    /// Set how long entries are kept at most. Defaults to 30 days.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Set how long a cached image is used without revalidating it. Defaults to 1 day.
+    pub fn revalidate_after(mut self, revalidate_after: Duration) -> Self {
+        self.revalidate_after = revalidate_after;
+        self
+    }
+
+    /// The directory the cache is stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Removes every cached entry.
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the HTML of every part, or `None` unless all of them are cached and current.
+    pub(crate) fn load_parts(
+        &self,
+        story_id: u64,
+        parts: &[PartStubResponse],
+    ) -> Option<HashMap<i64, String>> {
+        parts
+            .iter()
+            .map(|part| Some((part.id? as i64, self.load_part(story_id, part)?)))
+            .collect()
+    }
+
+    /// Returns the HTML of a part if it is cached and its `modify_date` is unchanged.
+    pub(crate) fn load_part(&self, story_id: u64, part: &PartStubResponse) -> Option<String> {
+        let (part_id, modify_date) = (part.id?, part.modify_date.as_deref()?);
+        let base = self.part_path(story_id, part_id);
+        let entry: PartEntry = read_entry(&base.with_extension("json"))?;
+        if entry.modify_date != modify_date || self.is_expired(entry.stored_at) {
+            return None;
+        }
+        fs::read_to_string(base.with_extension("html")).ok()
+    }
+
+    /// Stores the HTML of every part that has a `modify_date` to validate it with.
+    pub(crate) fn store_parts(
+        &self,
+        story_id: u64,
+        parts: &[PartStubResponse],
+        html_map: &HashMap<i64, String>,
+    ) {
+        for part in parts {
+            if let Some(html) = part.id.and_then(|id| html_map.get(&(id as i64))) {
+                self.store_part(story_id, part, html);
+            }
+        }
+    }
+
+    /// Stores the HTML of a part, unless it has no `modify_date` to validate it with.
+    pub(crate) fn store_part(&self, story_id: u64, part: &PartStubResponse, html: &str) {
+        let (Some(part_id), Some(modify_date)) = (part.id, part.modify_date.clone()) else {
+            return;
+        };
+        let base = self.part_path(story_id, part_id);
+        let entry = PartEntry {
+            modify_date,
+            stored_at: now(),
+        };
+        if let Err(e) = write_entry(&base, "html", html.as_bytes(), &entry) {
+            warn!(error = %e, part_id, "Failed to cache chapter content");
+        }
+    }
+
+    /// Looks up an image, ignoring expired entries.
+    pub(crate) fn load_image(&self, url: &str) -> Option<CachedImage> {
+        let base = self.image_path(url);
+        let entry: ImageEntry = read_entry(&base.with_extension("json"))?;
+        // Different URLs may share a hash.
+        if entry.url != url || self.is_expired(entry.stored_at) {
+            return None;
+        }
+        let data = fs::read(base.with_extension("bin")).ok()?;
+        Some(CachedImage {
+            data,
+            fresh: now().saturating_sub(entry.stored_at) < self.revalidate_after.as_secs(),
+            validators: entry.validators,
+        })
+    }
+
+    /// Stores an image along with its validators, restarting its revalidation period.
+    pub(crate) fn store_image(&self, url: &str, data: &[u8], validators: ImageValidators) {
+        let base = self.image_path(url);
+        let entry = ImageEntry {
+            url: url.to_string(),
+            validators,
+            stored_at: now(),
+        };
+        if let Err(e) = write_entry(&base, "bin", data, &entry) {
+            warn!(error = %e, url, "Failed to cache image");
+        }
+    }
+
+    fn is_expired(&self, stored_at: u64) -> bool {
+        now().saturating_sub(stored_at) >= self.max_age.as_secs()
+    }
+
+    fn part_path(&self, story_id: u64, part_id: u64) -> PathBuf {
+        self.dir
+            .join("parts")
+            .join(story_id.to_string())
+            .join(part_id.to_string())
+    }
+
+    fn image_path(&self, url: &str) -> PathBuf {
+        self.dir.join("images").join(format!("{:016x}", fnv1a(url)))
+    }
+}
+
+fn read_entry<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    let bytes = fs::read(path).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Writes the data file before its entry, so a half-written pair is never picked up.
+fn write_entry<T: Serialize>(
+    base: &Path,
+    extension: &str,
+    data: &[u8],
+    entry: &T,
+) -> io::Result<()> {
+    if let Some(parent) = base.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomically(&base.with_extension(extension), data)?;
+    write_atomically(&base.with_extension("json"), &serde_json::to_vec(entry)?)
+}
+
+/// Writes `data` to a temporary file of its own next to `path`, then moves it into place,
+/// so concurrent writers of the same path never mix their data.
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(data)?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A hash that stays stable across builds, unlike `DefaultHasher`.
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_writes_never_mix() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.bin");
+        std::thread::scope(|scope| {
+            for byte in 0..8u8 {
+                let path = &path;
+                scope.spawn(move || write_atomically(path, &[byte; 64 * 1024]).unwrap());
+            }
+        });
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 64 * 1024);
+        assert!(data.iter().all(|&byte| byte == data[0]));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,31 +1,192 @@
+use crate::report::{ImageFailure, MissingReason};
+use iepub::prelude::IError;
+use lol_html::errors::RewritingError;
 use thiserror::Error;
+use wp_mini::WattpadError;
 
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Authentication failed: invalid username or password")]
-    AuthenticationFailed,
+    AuthenticationFailed {
+        #[source]
+        source: WattpadError,
+    },
 
     #[error("User is not logged in")]
-    NotLoggedIn,
+    NotLoggedIn {
+        #[source]
+        source: WattpadError,
+    },
 
     #[error("Failed to log out")]
-    LogoutFailed,
+    LogoutFailed {
+        #[source]
+        source: WattpadError,
+    },
 
-    #[error("Story with ID {0} could not be found")]
-    StoryNotFound(i32),
+    #[error("Story with ID {story_id} could not be found")]
+    StoryNotFound { story_id: u64 },
 
-    #[error("Failed to fetch story metadata from Wattpad")]
-    MetadataFetchFailed,
+    #[error("`{input}` is not a link to a Wattpad story or part")]
+    UnsupportedLink { input: String },
 
-    #[error("Failed to download story content")]
-    DownloadFailed,
+    #[error("Part with ID {part_id} could not be found or does not belong to a story")]
+    PartNotFound { part_id: u64 },
 
-    #[error("Failed to process chapter content")]
-    ChapterProcessingFailed,
+    #[error("Failed to look up the story of part {part_id}")]
+    PartLookupFailed {
+        part_id: u64,
+        #[source]
+        source: WattpadError,
+    },
 
-    #[error("Failed to generate the EPUB file")]
-    EpubGenerationFailed,
+    #[error("User {username} could not be found")]
+    UserNotFound { username: String },
+
+    #[error("Failed to fetch the {list}")]
+    StoryListFetchFailed {
+        list: String,
+        #[source]
+        source: WattpadError,
+    },
+
+    #[error("Failed to fetch metadata of story {story_id} from Wattpad")]
+    MetadataFetchFailed {
+        story_id: u64,
+        #[source]
+        source: WattpadError,
+    },
+
+    #[error("Metadata of story {story_id} is missing the `{field}` field")]
+    MetadataIncomplete { story_id: u64, field: &'static str },
+
+    #[error("Story {story_id} has no parts")]
+    StoryHasNoParts { story_id: u64 },
+
+    #[error("None of the parts of story {story_id} match the part selection")]
+    NoPartsSelected { story_id: u64 },
+
+    #[error("Part {part_id} of story {story_id} has no content: {reason}")]
+    ChapterMissing {
+        story_id: u64,
+        part_id: u64,
+        reason: MissingReason,
+    },
+
+    #[error("None of the {failed} chapters of story {story_id} could be processed")]
+    NoChaptersProcessed { story_id: u64, failed: usize },
+
+    #[error("Failed to download content of story {story_id}")]
+    DownloadFailed {
+        story_id: u64,
+        #[source]
+        source: WattpadError,
+    },
+
+    #[error("Content archive of story {story_id} could not be read")]
+    ContentArchiveInvalid {
+        story_id: u64,
+        #[source]
+        source: zip::result::ZipError,
+    },
+
+    #[error("Failed to process chapter {index} (part {part_id})")]
+    ChapterProcessingFailed {
+        part_id: u64,
+        index: usize,
+        #[source]
+        source: HtmlError,
+    },
+
+    #[error("Failed to download image {url} of chapter {index}")]
+    ImageDownloadFailed {
+        index: usize,
+        url: String,
+        #[source]
+        source: ImageFailure,
+    },
+
+    #[error("Failed to download the cover of story {story_id}")]
+    CoverDownloadFailed {
+        story_id: u64,
+        #[source]
+        source: ImageFailure,
+    },
+
+    #[error("Failed to generate the EPUB file for story {story_id}")]
+    EpubGenerationFailed {
+        story_id: u64,
+        #[source]
+        source: IError,
+    },
+
+    #[error("Failed to read the existing EPUB")]
+    EpubReadFailed {
+        #[source]
+        source: IError,
+    },
+
+    #[error("The existing EPUB was generated from story {found}, not story {story_id}")]
+    EpubStoryMismatch { story_id: u64, found: u64 },
+
+    #[error("The existing EPUB is an omnibus of several stories and cannot be updated")]
+    EpubIsOmnibus,
+
+    #[error("The existing EPUB is volume {volume} of a split story and cannot be updated")]
+    EpubIsVolume { volume: usize },
+
+    #[error("`{file_name}` is not a valid font file name: use a `.ttf` file name without folders")]
+    InvalidFontFileName { file_name: String },
+
+    #[error("`{replacement}` is not allowed in file names and cannot replace other characters")]
+    InvalidFileNameReplacement { replacement: char },
+
+    #[error("Several volumes would be saved as `{file_name}`")]
+    FileNameCollision { file_name: String },
+
+    #[error("An omnibus needs at least one story")]
+    OmnibusEmpty,
+
+    #[error("The download was cancelled")]
+    Cancelled,
 
     #[error("An I/O error occurred")]
     IoError(#[from] std::io::Error),
-}
\ No newline at end of file
+}
+
+impl AppError {
+    /// Maps a `WattpadError` from a story request, singling out the errors
+    /// that have a dedicated variant (missing story, missing login).
+    pub(crate) fn from_story_request(
+        story_id: u64,
+        source: WattpadError,
+        fallback: impl FnOnce(u64, WattpadError) -> AppError,
+    ) -> AppError {
+        match source {
+            WattpadError::StoryNotFound => AppError::StoryNotFound { story_id },
+            WattpadError::PermissionDeniedNotLoggedIn
+            | WattpadError::AuthenticationRequired { .. } => AppError::NotLoggedIn { source },
+            source => fallback(story_id, source),
+        }
+    }
+}
+
+/// Errors raised while rewriting chapter HTML into XHTML.
+#[derive(Error, Debug)]
+pub enum HtmlError {
+    #[error("Failed to rewrite chapter HTML")]
+    Rewrite(#[from] RewritingError),
+
+    #[error("XML parsing error at position {position}")]
+    Xml {
+        position: u64,
+        #[source]
+        source: quick_xml::Error,
+    },
+
+    #[error("Failed to write XHTML")]
+    Write(#[from] std::io::Error),
+
+    #[error("Rewritten chapter is not valid UTF-8")]
+    Encoding(#[from] std::string::FromUtf8Error),
+}
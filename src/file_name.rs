@@ -0,0 +1,398 @@
+use crate::error::AppError;
+use crate::lang_util;
+use crate::processor::{now_timestamp, story_author, story_language_id};
+use icu_normalizer::DecomposingNormalizerBorrowed;
+use sanitize_filename::{
+    Options, OptionsForCheck, is_sanitized_with_options, sanitize_with_options,
+};
+use wp_mini::types::StoryResponse;
+
+/// The bytes left for a file name, without the extension, within the 255 bytes most file
+/// systems allow: files are written as `.{name}.epub.part` first, see
+/// [`crate::processor::write_epub_file`].
+const MAX_NAME_BYTES: usize = 255 - ".epub".len() - "..part".len();
+
+/// How EPUB files are named, without the `.epub` extension.
+///
+/// The template may contain these placeholders:
+/// * `{id}` - The Wattpad story ID.
+/// * `{title}` - The title of the book.
+/// * `{author}` - The username of the author.
+/// * `{language}` - The language code of the story, e.g. `en`.
+/// * `{parts}` - The number of parts of the story.
+/// * `{status}` - `Completed` or `Ongoing`.
+/// * `{date}` - The download date, e.g. `2024-01-31`.
+///
+/// Characters that are not allowed in file names are replaced, in the values and in the
+/// template alike, and a name left empty falls back to the story ID. Volumes of split
+/// stories end in ` Vol. {n}`. Omnibus books use the ID and details of their first story.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileNameTemplate {
+    template: String,
+    max_length: Option<usize>,
+    ascii: bool,
+    replacement: char,
+}
+
+impl Default for FileNameTemplate {
+    /// `{id}-{title}`, with `_` as the replacement character.
+    fn default() -> Self {
+        FileNameTemplate {
+            template: "{id}-{title}".to_string(),
+            max_length: None,
+            ascii: false,
+            replacement: '_',
+        }
+    }
+}
+
+impl FileNameTemplate {
+    /// Creates a template, e.g. `{author} - {title} ({id})`.
+    pub fn new(template: impl Into<String>) -> Self {
+        FileNameTemplate {
+            template: template.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the maximum length of the file name in characters, without the extension.
+    /// Longer names are cut, keeping the volume number of split stories. Defaults to no
+    /// limit other than the 255 bytes most file systems allow, less the room taken by the
+    /// `.epub` extension and the temporary file written first.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    /// Set whether the values are transliterated to ASCII. Defaults to `false`.
+    ///
+    /// Accents are dropped and Greek and Cyrillic letters are spelled out in Latin ones;
+    /// characters of other scripts are left out.
+    pub fn ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+
+    /// Set the character replacing those not allowed in file names. Defaults to `_`.
+    ///
+    /// # Returns
+    /// A `Result` containing the template, or [`AppError::InvalidFileNameReplacement`]
+    /// when `replacement` is itself not allowed in file names, e.g. `/` or `.`.
+    pub fn replacement(mut self, replacement: char) -> Result<Self, AppError> {
+        // Checked with the rules of Windows, whose file names are the strictest.
+        let check = OptionsForCheck {
+            windows: true,
+            truncate: true,
+        };
+        if replacement.is_control() || !is_sanitized_with_options(replacement.to_string(), check) {
+            return Err(AppError::InvalidFileNameReplacement { replacement });
+        }
+        self.replacement = replacement;
+        Ok(self)
+    }
+
+    /// The template the file names are built from.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Whether the story metadata has to include the completion status.
+    pub(crate) fn needs_status(&self) -> bool {
+        self.template.contains("{status}")
+    }
+
+    /// The file name of a book, without the extension.
+    ///
+    /// Volumes of split stories render the title of the whole story, and get their
+    /// number appended after any cut, so that no two volumes share a name.
+    pub(crate) fn render(
+        &self,
+        story_id: u64,
+        story: &StoryResponse,
+        title: &str,
+        volume: Option<usize>,
+    ) -> String {
+        let id = story_id.to_string();
+        let value = |placeholder: &str| -> Option<String> {
+            let value = match placeholder {
+                "id" => id.clone(),
+                "title" => title.to_string(),
+                "author" => story_author(story).to_string(),
+                "language" => lang_util::get_lang_code(story_language_id(story)).to_string(),
+                "parts" => story
+                    .num_parts
+                    .or_else(|| story.parts.as_ref().map(|parts| parts.len() as u64))
+                    .unwrap_or_default()
+                    .to_string(),
+                "status" => match story.completed {
+                    Some(true) => "Completed",
+                    Some(false) => "Ongoing",
+                    None => "Unknown",
+                }
+                .to_string(),
+                "date" => {
+                    let timestamp = now_timestamp();
+                    timestamp.get(..10).unwrap_or(&timestamp).to_string()
+                }
+                _ => return None,
+            };
+            Some(if self.ascii {
+                transliterate(&value)
+            } else {
+                value
+            })
+        };
+
+        // Substituted in one pass, so values are never taken for placeholders.
+        let mut name = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            name.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let substituted = rest
+                .find('}')
+                .and_then(|end| Some((end, value(&rest[1..end])?)));
+            match substituted {
+                Some((end, value)) => {
+                    name.push_str(&self.sanitize(&value));
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    name.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        name.push_str(rest);
+
+        let mut name = self.sanitize(&name);
+        if name.trim().is_empty() {
+            // Nothing was left of the values, e.g. a title in a script `ascii` drops.
+            name = id;
+        }
+        let suffix = volume.map(|number| format!(" Vol. {}", number));
+        let suffix = suffix.as_deref().unwrap_or_default();
+        let max_chars = self.max_length.map_or(usize::MAX, |max_length| {
+            max_length.saturating_sub(suffix.chars().count())
+        });
+        let max_bytes = MAX_NAME_BYTES - suffix.len();
+        let end = name
+            .char_indices()
+            .map(|(start, c)| start + c.len_utf8())
+            .take(max_chars)
+            .take_while(|&end| end <= max_bytes)
+            .last()
+            .unwrap_or_default();
+        if end < name.len() {
+            name.truncate(end);
+            // Cutting may leave a trailing space or dot, which Windows does not allow.
+            name.truncate(name.trim_end_matches([' ', '.']).len());
+        }
+        name.push_str(if name.is_empty() {
+            suffix.trim_start()
+        } else {
+            suffix
+        });
+        name
+    }
+
+    fn sanitize(&self, name: &str) -> String {
+        sanitize_with_options(
+            name,
+            Options {
+                replacement: &self.replacement.to_string(),
+                ..Default::default() // Use default values for other options like `windows` and `truncate`
+            },
+        )
+    }
+}
+
+/// Spells `text` in ASCII, see [`FileNameTemplate::ascii`].
+fn transliterate(text: &str) -> String {
+    let decomposed = DecomposingNormalizerBorrowed::new_nfkd().normalize(text);
+    let mut ascii = String::with_capacity(decomposed.len());
+    for c in decomposed.chars() {
+        if c.is_ascii() {
+            ascii.push(c);
+        } else if let Some(latin) = latin_spelling(c) {
+            // Keep the case of capitals, e.g. `Ж` becomes `Zh`.
+            let mut latin = latin.chars();
+            if c.is_uppercase()
+                && let Some(first) = latin.next()
+            {
+                ascii.push(first.to_ascii_uppercase());
+            }
+            ascii.extend(latin);
+        }
+    }
+    ascii
+}
+
+/// The lowercase Latin spelling of a letter that does not decompose into one.
+fn latin_spelling(c: char) -> Option<&'static str> {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    let latin = match lower {
+        'æ' => "ae",
+        'ð' => "d",
+        'đ' => "d",
+        'ħ' => "h",
+        'ı' => "i",
+        'ł' => "l",
+        'ø' => "o",
+        'œ' => "oe",
+        'ß' => "ss",
+        'þ' => "th",
+        // Greek
+        'α' => "a",
+        'β' => "v",
+        'γ' => "g",
+        'δ' => "d",
+        'ε' => "e",
+        'ζ' => "z",
+        'η' => "i",
+        'θ' => "th",
+        'ι' => "i",
+        'κ' => "k",
+        'λ' => "l",
+        'μ' => "m",
+        'ν' => "n",
+        'ξ' => "x",
+        'ο' => "o",
+        'π' => "p",
+        'ρ' => "r",
+        'σ' | 'ς' => "s",
+        'τ' => "t",
+        'υ' => "y",
+        'φ' => "f",
+        'χ' => "ch",
+        'ψ' => "ps",
+        'ω' => "o",
+        // Cyrillic
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' => "g",
+        'ґ' => "g",
+        'д' => "d",
+        'е' => "e",
+        'є' => "ye",
+        'ж' => "zh",
+        'з' => "z",
+        'и' => "i",
+        'і' => "i",
+        'к' => "k",
+        'л' => "l",
+        'м' => "m",
+        'н' => "n",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'у' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ъ' | 'ь' => "",
+        'ы' => "y",
+        'э' => "e",
+        'ю' => "yu",
+        'я' => "ya",
+        // Punctuation
+        '‘' | '’' => "'",
+        '“' | '”' => "\"",
+        '–' | '—' => "-",
+        _ => return None,
+    };
+    Some(latin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn story() -> StoryResponse {
+        serde_json::from_str(
+            r#"{"title": "Ignored", "user": {"name": "author"}, "completed": true, "numParts": 3}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn renders_placeholders_in_one_pass() {
+        let template = FileNameTemplate::new("{author} - {title} [{status}, {parts}] {unknown}");
+        assert_eq!(
+            template.render(1, &story(), "{id}", None),
+            "author - {id} [Completed, 3] {unknown}"
+        );
+        let template = FileNameTemplate::new("{{id}}: {title}/");
+        assert_eq!(template.render(1, &story(), "a/b", None), "{1}_ a_b_");
+    }
+
+    #[test]
+    fn transliterates_to_ascii() {
+        assert_eq!(transliterate("Crème Brûlée"), "Creme Brulee");
+        assert_eq!(transliterate("Жизнь — Ωμέγα"), "Zhizn - Omega");
+        assert_eq!(transliterate("Straße Æsir"), "Strasse Aesir");
+        assert_eq!(transliterate("星の物語"), "");
+    }
+
+    #[test]
+    fn falls_back_to_the_story_id() {
+        let template = FileNameTemplate::new("{title}").ascii(true);
+        assert_eq!(template.render(42, &story(), "星の物語", None), "42");
+        assert_eq!(
+            template.render(42, &story(), "星の物語", Some(2)),
+            "42 Vol. 2"
+        );
+    }
+
+    #[test]
+    fn keeps_the_volume_number_when_cutting() {
+        let template = FileNameTemplate::new("{title}").max_length(12);
+        assert_eq!(
+            template.render(1, &story(), "Long Title. Here", None),
+            "Long Title"
+        );
+        assert_eq!(
+            template.render(1, &story(), "Long Title. Here", Some(2)),
+            "Long Vol. 2"
+        );
+        assert_eq!(
+            template.render(1, &story(), "Short", Some(10)),
+            "Shor Vol. 10"
+        );
+        assert_eq!(
+            FileNameTemplate::new("{title}")
+                .max_length(3)
+                .render(1, &story(), "Title", Some(2)),
+            "Vol. 2"
+        );
+    }
+
+    #[test]
+    fn leaves_room_for_the_extension_and_the_temporary_file() {
+        let title = "物語".repeat(50); // 300 bytes
+        let name = FileNameTemplate::new("{title}").render(1, &story(), &title, Some(12));
+        assert!(name.ends_with("語 Vol. 12"), "{}", name);
+        assert!(name.len() <= MAX_NAME_BYTES);
+        assert!(format!(".{}.epub.part", name).len() <= 255);
+    }
+
+    #[test]
+    fn rejects_replacements_not_allowed_in_file_names() {
+        for replacement in ['/', '\\', ':', '.', ' ', '\n', '\u{85}'] {
+            assert!(
+                FileNameTemplate::default()
+                    .replacement(replacement)
+                    .is_err(),
+                "{:?}",
+                replacement
+            );
+        }
+        assert!(FileNameTemplate::default().replacement('-').is_ok());
+    }
+}
@@ -1,10 +1,10 @@
-use anyhow::{anyhow, Context, Result};
+use crate::error::HtmlError;
 use lol_html::{element, html_content::ContentType, HtmlRewriter, Settings};
 use quick_xml::{events::Event, Reader, Writer};
 use std::collections::HashMap;
 use std::io::Cursor;
 use std::sync::{Arc, Mutex};
-pub(super) fn re_encode_html(html_fragment: &str) -> Result<String> {
+pub(super) fn re_encode_html(html_fragment: &str) -> Result<String, HtmlError> {
     let wrapped_html = format!("<root>{}</root>", html_fragment);
     let mut reader = Reader::from_str(&wrapped_html);
     let config = reader.config_mut();
@@ -25,11 +25,10 @@ pub(super) fn re_encode_html(html_fragment: &str) -> Result<String> {
                 writer.write_event(e)?;
             }
             Err(e) => {
-                return Err(anyhow!(
-                    "XML parsing error at position {}: {:?}",
-                    reader.buffer_position(),
-                    e
-                ));
+                return Err(HtmlError::Xml {
+                    position: reader.buffer_position(),
+                    source: e,
+                });
             }
         }
     }
@@ -42,7 +41,7 @@ pub(super) fn rewrite_and_clean_html(
     html_in: &str,
     embed_images: bool,
     image_map: &HashMap<String, String>,
-) -> Result<String> {
+) -> Result<String, HtmlError> {
     let output_buffer = Arc::new(Mutex::new(String::new()));
     let output_clone = Arc::clone(&output_buffer);
 
@@ -99,10 +98,10 @@ pub(super) fn rewrite_and_clean_html(
 
     let cleaned_html = output_buffer.lock().unwrap().clone();
 
-    re_encode_html(&cleaned_html).context("Failed to re-encode HTML for XML compatibility")
+    re_encode_html(&cleaned_html)
 }
 
-pub(super) fn collect_image_urls(html: &str) -> Result<Vec<String>> {
+pub(super) fn collect_image_urls(html: &str) -> Result<Vec<String>, HtmlError> {
     let urls = Arc::new(Mutex::new(Vec::new()));
     let urls_clone = Arc::clone(&urls);
     let mut rewriter = HtmlRewriter::new(
@@ -119,7 +118,112 @@ pub(super) fn collect_image_urls(html: &str) -> Result<Vec<String>> {
     );
     rewriter.write(html.as_bytes())?;
     rewriter.end()?;
-    Ok(Arc::try_unwrap(urls).unwrap().into_inner()?)
+    Ok(Arc::try_unwrap(urls)
+        .unwrap()
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Roughly counts the words in an HTML fragment, treating every tag as a word boundary.
+pub(super) fn count_words(html: &str) -> usize {
+    let (mut count, mut in_tag, mut in_word) = (0, false, false);
+    for c in html.chars() {
+        match c {
+            '<' => (in_tag, in_word) = (true, false),
+            '>' if in_tag => in_tag = false,
+            _ if in_tag => {}
+            c if c.is_whitespace() => in_word = false,
+            _ if !in_word => (count, in_word) = (count + 1, true),
+            _ => {}
+        }
+    }
+    count
+}
+
+/// Removes the first paragraph of a chapter when it only repeats the chapter title.
+///
+/// Empty paragraphs before it are skipped; anything else, such as an image, stops the
+/// search. See [`titles_match`] for what counts as a repetition.
+pub(super) fn remove_duplicate_title(html: &str, title: &str) -> String {
+    let mut rest = html;
+    loop {
+        let trimmed = rest.trim_start();
+        let is_paragraph = trimmed.starts_with("<p>") || trimmed.starts_with("<p ");
+        let Some(end) = trimmed.find("</p>").filter(|_| is_paragraph) else {
+            return html.to_string();
+        };
+        let paragraph = &trimmed[..end];
+        if paragraph.contains("<img") {
+            return html.to_string();
+        }
+
+        let text = text_content(paragraph);
+        let after = &trimmed[end + "</p>".len()..];
+        if text.trim().is_empty() {
+            rest = after;
+        } else if titles_match(&text, title) {
+            let start = html.len() - trimmed.len();
+            return format!("{}{}", &html[..start], after.trim_start());
+        } else {
+            return html.to_string();
+        }
+    }
+}
+
+/// The text of an HTML fragment, without its tags.
+fn text_content(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    quick_xml::escape::unescape(&text)
+        .map(|text| text.into_owned())
+        .unwrap_or(text)
+}
+
+/// Whether a paragraph repeats a title, ignoring case, punctuation, whitespace and a
+/// leading chapter number such as `Chapter 3:`, `Part 3 -` or `3.`.
+fn titles_match(paragraph: &str, title: &str) -> bool {
+    let (paragraph, title) = (normalize_title(paragraph), normalize_title(title));
+    if paragraph.is_empty() || title.is_empty() {
+        return false;
+    }
+    if paragraph == title {
+        return true;
+    }
+    let (paragraph, title) = (
+        strip_chapter_number(&paragraph),
+        strip_chapter_number(&title),
+    );
+    !paragraph.is_empty() && paragraph == title
+}
+
+/// Lowercases a title and keeps only its letters and digits.
+fn normalize_title(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Strips a leading chapter number from a normalized title.
+fn strip_chapter_number(title: &str) -> &str {
+    for label in ["chapter", "part", "ch"] {
+        if let Some(rest) = title.strip_prefix(label) {
+            let number = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+            if number.len() < rest.len() {
+                return number;
+            }
+        }
+    }
+    title.trim_start_matches(|c: char| c.is_ascii_digit())
 }
 
 pub(super) fn infer_extension_from_data(data: &[u8]) -> Option<&str> {
@@ -130,3 +234,48 @@ pub(super) fn infer_extension_from_data(data: &[u8]) -> Option<&str> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_a_numbered_title() {
+        assert_eq!(
+            remove_duplicate_title("<p>Chapter 3: The Storm</p><p>Rain.</p>", "The Storm"),
+            "<p>Rain.</p>"
+        );
+        assert_eq!(
+            remove_duplicate_title("<p>The Storm</p><p>Rain.</p>", "Part 3 - The Storm"),
+            "<p>Rain.</p>"
+        );
+    }
+
+    #[test]
+    fn ignores_case_and_whitespace() {
+        assert_eq!(
+            remove_duplicate_title(
+                "\n<p> </p>\n<p style=\"text-align: center\"><b>THE  storm</b></p>\n<p>Rain.</p>",
+                "The Storm"
+            ),
+            "\n<p> </p>\n<p>Rain.</p>"
+        );
+    }
+
+    #[test]
+    fn keeps_a_paragraph_starting_with_the_title() {
+        let html = "<p>The Storm came at night.</p><p>Rain.</p>";
+        assert_eq!(remove_duplicate_title(html, "The Storm"), html);
+        let html = "<p><img src=\"a.jpg\"/>The Storm</p>";
+        assert_eq!(remove_duplicate_title(html, "The Storm"), html);
+    }
+
+    #[test]
+    fn keeps_everything_for_an_empty_title() {
+        let html = "<p></p><p>Rain.</p>";
+        assert_eq!(remove_duplicate_title(html, ""), html);
+        assert_eq!(remove_duplicate_title(html, "  ?! "), html);
+        // Nothing but the numbers is left to compare.
+        assert!(!titles_match("Chapter 3", "Chapter 4"));
+    }
+}
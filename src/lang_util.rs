@@ -76,6 +76,7 @@ pub(crate) fn get_direction_for_lang_id(lang_id: u64) -> Direction {
 
 /// Maps Language Codes to a text direction (LTR or RTL).
 /// Defaults to LTR.
+#[allow(dead_code)]
 pub(crate) fn get_direction_for_lang_code(lang_code: &str) -> Direction {
     match lang_code {
         "ar" | "he" | "fa" | "ur" => Direction::RTL, // Arabic, Hebrew, Persian, Urdu
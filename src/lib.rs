@@ -1,20 +1,66 @@
 // Keep modules private to the crate
 mod auth;
+mod batch;
+#[cfg(not(target_arch = "wasm32"))]
+mod cache;
 mod html;
 mod models;
+mod omnibus;
 mod processor;
 mod error;
+mod file_name;
 mod types;
 mod lang_util;
+mod link;
+mod options;
+mod plan;
+mod progress;
+mod rate_limit;
+mod report;
+mod retry;
+mod selection;
+#[cfg(not(target_arch = "wasm32"))]
+mod story_list;
+mod style;
+mod title_page;
+mod toc;
+mod update;
+mod volume;
 
 // Expose own items
 pub use auth::{login, logout};
-pub use error::AppError;
+pub use batch::{BatchItem, BatchSummary, StoryBatch};
+#[cfg(not(target_arch = "wasm32"))]
+pub use cache::DownloadCache; // Only expose `DownloadCache` in non-WASM builds
+pub use error::{AppError, HtmlError};
+pub use file_name::FileNameTemplate;
+pub use link::{resolve_story_id, WattpadLink};
+pub use omnibus::{OmnibusDownload, OmnibusStory};
 pub use crate::types::StoryDownload;
+pub use options::{ChapterHeading, DownloadOptions, DownloadOptionsBuilder, Strictness};
+pub use plan::{PlannedPart, StoryPlan};
+pub use progress::ProgressEvent;
+pub use rate_limit::{RateLimiter, RateLimiterBuilder};
+pub use retry::RetryPolicy;
+pub use selection::PartSelection;
+pub use style::{EmbeddedFont, Theme};
+pub use volume::VolumeSplit;
+#[cfg(not(target_arch = "wasm32"))]
+pub use story_list::{
+    ManifestEntry, ManifestStatus, StoryList, StoryListDownload, StoryListManifest,
+    MANIFEST_FILE_NAME,
+}; // Only expose story lists in non-WASM builds
+pub use report::{
+    CoverStatus, DownloadReport, FailedChapter, ImageFailure, MissingChapter, MissingReason,
+    PlaceholderImage,
+};
+pub use tokio_util::sync::CancellationToken; // Accepted by `DownloadOptions`, so re-export it.
 
 // Re-export the necessary types from the wp-mini crate
-pub use wp_mini::field::StoryField;
+pub use wp_mini::field::{PartStubField, StoryField};
 pub use wp_mini::types::StoryResponse; // We return this, so re-export it too!
+pub use wp_mini::types::PartStubResponse; // Seen by `PartSelection::filter`
+pub use wp_mini::WattpadError; // Carried as the source of several `AppError`s
 
 // Be explicit with the processor module's public API
 #[cfg(not(target_arch = "wasm32"))]
@@ -22,17 +68,74 @@ pub use processor::download_story_to_file; // Only expose `download_story_to_fil
 #[cfg(not(target_arch = "wasm32"))]
 pub use processor::download_story_to_folder; // Only expose `download_story_to_folder` in non-WASM builds
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use processor::download_story_to_async_writer; // Only expose `download_story_to_async_writer` in non-WASM builds
+
 pub use processor::download_story_to_memory;
+pub use processor::download_story_to_writer;
+
+// The update API mirrors the download API
+#[cfg(not(target_arch = "wasm32"))]
+pub use update::update_story_epub; // Only expose `update_story_epub` in non-WASM builds
+pub use update::update_story_epub_in_memory;
+
+// The two-phase API: fetch a plan first, then build the EPUB from it
+pub use plan::{build_from_plan, fetch_story_plan};
+
+// Batch downloads of several stories sharing their resources
+#[cfg(not(target_arch = "wasm32"))]
+pub use batch::download_stories_to_folder; // Only expose `download_stories_to_folder` in non-WASM builds
+pub use batch::download_stories_to_memory;
+
+// Several stories combined into one book
+#[cfg(not(target_arch = "wasm32"))]
+pub use omnibus::download_omnibus_to_folder; // Only expose `download_omnibus_to_folder` in non-WASM builds
+pub use omnibus::download_omnibus_to_memory;
+
+// Downloads of whole reading lists and user profiles
+#[cfg(not(target_arch = "wasm32"))]
+pub use story_list::{download_story_list_to_folder, fetch_story_list_ids}; // Only expose story lists in non-WASM builds
 
 // Prelude would then also be explicit
 pub mod prelude {
     pub use crate::auth::{login, logout};
-    pub use crate::error::AppError;
+    pub use crate::batch::{BatchItem, BatchSummary, StoryBatch};
+
+    // Only expose `DownloadCache` in non-WASM builds
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use crate::cache::DownloadCache;
+
+    pub use crate::error::{AppError, HtmlError};
+    pub use crate::file_name::FileNameTemplate;
+    pub use crate::link::{resolve_story_id, WattpadLink};
+    pub use crate::omnibus::{OmnibusDownload, OmnibusStory};
     pub use crate::types::StoryDownload;
+    pub use crate::options::{ChapterHeading, DownloadOptions, DownloadOptionsBuilder, Strictness};
+    pub use crate::plan::{PlannedPart, StoryPlan};
+    pub use crate::progress::ProgressEvent;
+    pub use crate::rate_limit::{RateLimiter, RateLimiterBuilder};
+    pub use crate::retry::RetryPolicy;
+    pub use crate::selection::PartSelection;
+    pub use crate::style::{EmbeddedFont, Theme};
+    pub use crate::volume::VolumeSplit;
+
+    // Only expose story lists in non-WASM builds
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use crate::story_list::{
+        ManifestEntry, ManifestStatus, StoryList, StoryListDownload, StoryListManifest,
+        MANIFEST_FILE_NAME,
+    };
+
+    pub use crate::report::{
+        CoverStatus, DownloadReport, FailedChapter, ImageFailure, MissingChapter, MissingReason,
+        PlaceholderImage,
+    };
+    pub use tokio_util::sync::CancellationToken;
 
     // Re-export from the prelude as well for convenience
-    pub use wp_mini::field::StoryField;
-    pub use wp_mini::types::StoryResponse;
+    pub use wp_mini::field::{PartStubField, StoryField};
+    pub use wp_mini::types::{PartStubResponse, StoryResponse};
+    pub use wp_mini::WattpadError;
 
     // Only expose `download_story_to_file` in non-WASM builds
     #[cfg(not(target_arch = "wasm32"))]
@@ -42,6 +145,34 @@ pub mod prelude {
     #[cfg(not(target_arch = "wasm32"))]
     pub use crate::processor::download_story_to_folder;
 
+    // Only expose `download_story_to_async_writer` in non-WASM builds
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use crate::processor::download_story_to_async_writer;
 
     pub use crate::processor::download_story_to_memory;
+    pub use crate::processor::download_story_to_writer;
+
+    // Only expose `update_story_epub` in non-WASM builds
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use crate::update::update_story_epub;
+
+    pub use crate::update::update_story_epub_in_memory;
+
+    pub use crate::plan::{build_from_plan, fetch_story_plan};
+
+    // Only expose `download_stories_to_folder` in non-WASM builds
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use crate::batch::download_stories_to_folder;
+
+    pub use crate::batch::download_stories_to_memory;
+
+    // Only expose `download_omnibus_to_folder` in non-WASM builds
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use crate::omnibus::download_omnibus_to_folder;
+
+    pub use crate::omnibus::download_omnibus_to_memory;
+
+    // Only expose story lists in non-WASM builds
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use crate::story_list::{download_story_list_to_folder, fetch_story_list_ids};
 }
\ No newline at end of file
@@ -0,0 +1,171 @@
+use crate::error::AppError;
+use crate::options::DownloadOptions;
+use crate::retry::retry;
+use std::str::FromStr;
+use tracing::{info, instrument};
+use wp_mini::field::PartField;
+use wp_mini::{WattpadClient, WattpadError};
+
+/// A link to a Wattpad story or part, parsed without any request.
+///
+/// Accepted inputs:
+/// - a bare story ID, e.g. `123`
+/// - story links, e.g. `https://www.wattpad.com/story/123-slug`
+/// - part links, e.g. `https://www.wattpad.com/456-part-slug` or `wattpad.com/amp/456`
+///
+/// The scheme is optional, any `wattpad.com` subdomain (`www.`, `m.`, ...) is accepted
+/// and query strings such as the tracking parameters of share links are ignored.
+/// Shortened `w.tt` links are not supported, as they can only be resolved by following
+/// their redirect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WattpadLink {
+    /// A story ID.
+    Story(u64),
+    /// A part ID, whose story still has to be looked up.
+    Part(u64),
+}
+
+impl WattpadLink {
+    /// Parses a link, failing with [`AppError::UnsupportedLink`] for anything else.
+    pub fn parse(input: &str) -> Result<Self, AppError> {
+        parse_link(input.trim()).ok_or_else(|| AppError::UnsupportedLink {
+            input: input.to_string(),
+        })
+    }
+}
+
+impl FromStr for WattpadLink {
+    type Err = AppError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::parse(input)
+    }
+}
+
+/// Resolves a story ID from a story ID, story link or part link.
+///
+/// See [`WattpadLink`] for the accepted inputs. Part links cost one request to look up
+/// the story the part belongs to; everything else is resolved without a request. That
+/// request goes through the rate limiter, retry policy and cancellation token of `options`.
+///
+/// # Arguments
+/// * `options` - The download settings, see [`DownloadOptions`].
+///
+/// # Returns
+/// A `Result` containing the story ID, ready to be passed to the `download_story_to_*` functions.
+#[instrument(skip(wattpad_client, options))]
+pub async fn resolve_story_id(
+    wattpad_client: &WattpadClient,
+    input: &str,
+    options: &DownloadOptions,
+) -> Result<u64, AppError> {
+    match WattpadLink::parse(input)? {
+        WattpadLink::Story(story_id) => Ok(story_id),
+        WattpadLink::Part(part_id) => {
+            let part = options
+                .cancellation
+                .run_until_cancelled(retry(&options.retry_policy, || {
+                    options.throttled(
+                        wattpad_client
+                            .story
+                            .get_part_info(part_id, Some(&[PartField::GroupId])),
+                    )
+                }))
+                .await
+                .ok_or(AppError::Cancelled)?
+                .map_err(|source| match source {
+                    WattpadError::StoryNotFound => AppError::PartNotFound { part_id },
+                    source => AppError::PartLookupFailed { part_id, source },
+                })?;
+
+            let story_id = part
+                .group_id
+                .and_then(|group_id| group_id.parse().ok())
+                .ok_or(AppError::PartNotFound { part_id })?;
+            info!(part_id, story_id, "Resolved the story of a part link");
+            Ok(story_id)
+        }
+    }
+}
+
+fn parse_link(input: &str) -> Option<WattpadLink> {
+    if !input.is_empty() && input.bytes().all(|b| b.is_ascii_digit()) {
+        return input.parse().ok().map(WattpadLink::Story);
+    }
+
+    let without_scheme = ["https://", "http://"]
+        .iter()
+        .find_map(|scheme| {
+            input
+                .get(..scheme.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(scheme))
+                .map(|_| &input[scheme.len()..])
+        })
+        .unwrap_or(input);
+    let without_query = without_scheme.split(['?', '#']).next().unwrap_or_default();
+
+    let mut segments = without_query.split('/').filter(|s| !s.is_empty());
+    let host = segments.next()?.to_ascii_lowercase();
+    if host != "wattpad.com" && !host.ends_with(".wattpad.com") {
+        return None;
+    }
+
+    let segment = segments.next()?;
+    if segment.eq_ignore_ascii_case("story") {
+        leading_id(segments.next()?).map(WattpadLink::Story)
+    } else if segment.eq_ignore_ascii_case("amp") {
+        leading_id(segments.next()?).map(WattpadLink::Part)
+    } else {
+        leading_id(segment).map(WattpadLink::Part)
+    }
+}
+
+/// Parses the ID at the start of a path segment such as `123-some-slug`.
+fn leading_id(segment: &str) -> Option<u64> {
+    let id = segment.split_once('-').map_or(segment, |(id, _)| id);
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    id.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_story_ids_and_links() {
+        let story = Some(WattpadLink::Story(123));
+        assert_eq!(parse_link("123"), story);
+        assert_eq!(parse_link("https://www.wattpad.com/story/123-some-slug"), story);
+        assert_eq!(parse_link("wattpad.com/story/123?utm_source=share"), story);
+        assert_eq!(parse_link("http://m.wattpad.com/story/123/parts"), story);
+    }
+
+    #[test]
+    fn parses_part_links() {
+        let part = Some(WattpadLink::Part(456));
+        assert_eq!(parse_link("https://www.wattpad.com/456-part-slug"), part);
+        assert_eq!(parse_link("wattpad.com/amp/456"), part);
+        assert_eq!(parse_link("https://www.wattpad.com/456#comments"), part);
+    }
+
+    #[test]
+    fn ignores_case_of_scheme_host_and_path() {
+        assert_eq!(
+            parse_link("HTTPS://WWW.WATTPAD.COM/Story/1"),
+            Some(WattpadLink::Story(1))
+        );
+        assert_eq!(parse_link("Wattpad.com/AMP/2"), Some(WattpadLink::Part(2)));
+    }
+
+    #[test]
+    fn rejects_other_inputs() {
+        assert_eq!(parse_link(""), None);
+        assert_eq!(parse_link("https://example.com/story/123"), None);
+        assert_eq!(parse_link("https://notwattpad.com/story/123"), None);
+        assert_eq!(parse_link("https://www.wattpad.com/user/someone"), None);
+        assert_eq!(parse_link("https://w.tt/abc"), None);
+        assert!(WattpadLink::parse("not a link").is_err());
+    }
+}
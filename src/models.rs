@@ -1,12 +1,201 @@
+use crate::report::{DownloadReport, ImageFailure};
+use iepub::prelude::{EpubBook, EpubMetaData};
+use sha2::{Digest, Sha256};
+use wp_mini::types::{PartStubResponse, StoryResponse};
+
+/// Name of the OPF `<meta>` recording which story an EPUB was generated from.
+pub(crate) const STORY_META_NAME: &str = "wp-mini-epub:story";
+/// Name of the OPF `<meta>` entries recording the part behind every chapter file.
+pub(crate) const PART_META_NAME: &str = "wp-mini-epub:part";
+/// Name of the OPF `<meta>` listing the stories an omnibus EPUB was generated from.
+pub(crate) const OMNIBUS_META_NAME: &str = "wp-mini-epub:omnibus";
+/// Name of the OPF `<meta>` recording which volume of a split story an EPUB holds.
+pub(crate) const VOLUME_META_NAME: &str = "wp-mini-epub:volume";
+/// Name of the OPF `<meta>` recording when an EPUB was generated.
+pub(crate) const DOWNLOADED_META_NAME: &str = "wp-mini-epub:downloaded";
+/// Name of the OPF `<meta>` recording the version of this crate that generated an EPUB.
+pub(crate) const VERSION_META_NAME: &str = "wp-mini-epub:version";
+/// Prefix of the hashes recorded for the content of every part.
+const CONTENT_HASH_PREFIX: &str = "sha256:";
+
+/// The fully processed story, ready to be serialized into any output.
+pub(super) struct PreparedStory {
+    pub(super) book: EpubBook,
+    pub(super) sanitized_title: String,
+    pub(super) metadata: StoryResponse,
+    pub(super) report: DownloadReport,
+}
+
+/// The volumes of a story split by [`crate::VolumeSplit`], ready to be serialized.
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+pub(super) struct PreparedVolumes {
+    /// One entry per volume, or a single entry for a story that was not split.
+    pub(super) volumes: Vec<PreparedVolume>,
+    /// The sanitized title of the whole story.
+    pub(super) sanitized_title: String,
+    pub(super) metadata: StoryResponse,
+    pub(super) report: DownloadReport,
+}
+
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+pub(super) struct PreparedVolume {
+    pub(super) book: EpubBook,
+    pub(super) sanitized_title: String,
+}
+
+/// A story whose chapters and cover were processed, ready to be added to a book.
+pub(super) struct ProcessedStory {
+    pub(super) story: StoryResponse,
+    /// The title naming the selected parts, see [`crate::PartSelection`].
+    pub(super) title: String,
+    /// The successfully processed chapters, sorted by index.
+    pub(super) chapters: Vec<ProcessedChapter>,
+    pub(super) cover: Option<Vec<u8>>,
+    pub(super) report: DownloadReport,
+}
+
+/// A chapter waiting to be processed.
+pub(super) struct ChapterJob {
+    pub(super) index: usize,
+    /// The 1-based position of the part in the story.
+    pub(super) position: usize,
+    pub(super) part_id: u64,
+    pub(super) title: String,
+    pub(super) modify_date: Option<String>,
+    /// Names the chapter file (`{file_stem}.xhtml`) and its image folder.
+    pub(super) file_stem: String,
+    pub(super) html: String,
+    /// Hash of the part's HTML as served by Wattpad.
+    pub(super) content_hash: String,
+}
+
+impl ChapterJob {
+    pub(super) fn new(
+        index: usize,
+        position: usize,
+        file_stem: String,
+        part: PartStubResponse,
+        html: String,
+    ) -> Self {
+        Self {
+            index,
+            position,
+            part_id: part.id.unwrap_or_default(),
+            title: part.title.unwrap_or_else(|| "Untitled Chapter".to_string()),
+            modify_date: part.modify_date,
+            file_stem,
+            content_hash: content_hash(&html),
+            html,
+        }
+    }
+}
+
 pub(super) struct ProcessedChapter {
     pub(super) index: usize,
+    /// The 1-based position of the part in the story.
+    pub(super) position: usize,
+    pub(super) part_id: u64,
+    pub(super) modify_date: Option<String>,
+    pub(super) content_hash: String,
     pub(super) title: String,
     pub(super) file_name: String,
     pub(super) html_content: String,
     pub(super) images: Vec<ImageAsset>,
+    pub(super) image_count: usize,
+    pub(super) failed_images: Vec<(String, ImageFailure)>,
+}
+
+impl ProcessedChapter {
+    pub(super) fn part_record(&self) -> PartRecord {
+        PartRecord {
+            part_id: self.part_id,
+            file_name: self.file_name.clone(),
+            modify_date: self.modify_date.clone(),
+            content_hash: Some(self.content_hash.clone()),
+        }
+    }
+}
+
+/// Links a chapter file to the Wattpad part it was generated from.
+///
+/// Stored in the OPF as
+/// `<meta name="wp-mini-epub:part" content="{part_id} {file_name} {modify_date} sha256:{hash}"/>`,
+/// where the modify date and hash are left out when unknown.
+pub(super) struct PartRecord {
+    pub(super) part_id: u64,
+    pub(super) file_name: String,
+    pub(super) modify_date: Option<String>,
+    /// `sha256:` followed by the hex digest of the part's source HTML.
+    pub(super) content_hash: Option<String>,
+}
+
+impl PartRecord {
+    pub(super) fn to_meta(&self) -> EpubMetaData {
+        let mut content = format!("{} {}", self.part_id, self.file_name);
+        if let Some(modify_date) = &self.modify_date {
+            content.push(' ');
+            content.push_str(modify_date);
+        }
+        if let Some(content_hash) = &self.content_hash {
+            content.push(' ');
+            content.push_str(content_hash);
+        }
+        EpubMetaData::default()
+            .with_attr("name", PART_META_NAME)
+            .with_attr("content", &content)
+    }
+
+    pub(super) fn from_meta(meta: &EpubMetaData) -> Option<Self> {
+        if meta.get_attr("name")? != PART_META_NAME {
+            return None;
+        }
+        let mut fields = meta.get_attr("content")?.split_whitespace();
+        let part_id = fields.next()?.parse().ok()?;
+        let file_name = fields.next()?.to_string();
+        let (mut modify_date, mut content_hash) = (None, None);
+        for field in fields {
+            if field.starts_with(CONTENT_HASH_PREFIX) {
+                content_hash = Some(field.to_string());
+            } else {
+                modify_date = Some(field.to_string());
+            }
+        }
+        Some(Self {
+            part_id,
+            file_name,
+            modify_date,
+            content_hash,
+        })
+    }
+}
+
+/// Hashes the source HTML of a part, so identical content can be recognized later.
+fn content_hash(html: &str) -> String {
+    format!(
+        "{}{:x}",
+        CONTENT_HASH_PREFIX,
+        Sha256::digest(html.as_bytes())
+    )
 }
 
 pub(super) struct ImageAsset {
     pub(super) epub_path: String,
     pub(super) data: Vec<u8>,
-}
\ No newline at end of file
+}
+/// The `ETag` and `Last-Modified` headers of an image response.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    not(target_arch = "wasm32"),
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub(crate) struct ImageValidators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+/// The outcome of a successful image request.
+pub(super) enum ImageResponse {
+    Downloaded(Vec<u8>, ImageValidators),
+    /// The server confirmed the cached copy is still current.
+    NotModified,
+}
@@ -0,0 +1,341 @@
+use crate::error::AppError;
+use crate::lang_util;
+use crate::models::ProcessedStory;
+use crate::options::DownloadOptions;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use crate::processor::write_epub_file;
+use crate::processor::{
+    add_processed_chapter, chapter_html, ensure_not_cancelled, fetch_processed_story,
+    finish_book_from, new_epub_builder, now_timestamp, sanitized_title, story_author,
+    story_language_id, write_epub,
+};
+use crate::progress::ProgressEvent;
+use crate::report::DownloadReport;
+use crate::style::add_stylesheet;
+use crate::title_page::title_page_body;
+use crate::toc::{TOC_PAGE_FILE_NAME, TocEntry, toc_page_body};
+use iepub::prelude::{EpubBook, EpubNav};
+use reqwest::Client;
+use std::collections::HashSet;
+use std::io::Cursor;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use std::path::{Path, PathBuf};
+use tracing::{info, instrument};
+use wp_mini::WattpadClient;
+use wp_mini::types::StoryResponse;
+
+/// The result of an omnibus download, combining several stories into one EPUB.
+pub struct OmnibusDownload<T> {
+    /// Sanitized Title ( Follow the [`crate::FileNameTemplate`], with the first story's details )
+    pub sanitized_title: String,
+    /// The generated EPUB file, either as a PathBuf or a Vec<u8>.
+    pub epub_response: T,
+    /// Every story of the omnibus, in book order.
+    pub stories: Vec<OmnibusStory>,
+}
+
+/// A story of an [`OmnibusDownload`].
+pub struct OmnibusStory {
+    /// The Wattpad story ID.
+    pub story_id: u64,
+    /// The full story metadata fetched from Wattpad.
+    pub metadata: StoryResponse,
+    /// What made it into the EPUB, and what was skipped or replaced.
+    pub report: DownloadReport,
+}
+
+/// The omnibus book, ready to be serialized into any output.
+struct PreparedOmnibus {
+    book: EpubBook,
+    sanitized_title: String,
+    stories: Vec<OmnibusStory>,
+}
+
+/// Downloads several stories into a single EPUB, saving it into `output_path`.
+///
+/// See [`download_omnibus_to_memory`] for how the book is laid out.
+///
+/// Excluded for wasm32
+///
+/// # Arguments
+/// * `story_ids` - The stories, in book order. Duplicates are included once.
+/// * `title` - The title of the omnibus.
+/// * `options` - The download settings, applied to every story.
+/// * `output_path` - The directory where the final `.epub` file will be saved.
+///
+/// # Returns
+/// A `Result` containing the full `PathBuf` to the generated file.
+#[cfg(not(target_arch = "wasm32"))]
+#[instrument(skip(wattpad_client, reqwest_client, options, output_path))]
+pub async fn download_omnibus_to_folder(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_ids: &[u64],
+    title: &str,
+    options: &DownloadOptions,
+    output_path: &Path,
+) -> Result<OmnibusDownload<PathBuf>, AppError> {
+    let prepared = options
+        .cancellation
+        .run_until_cancelled(prepare_omnibus(
+            wattpad_client,
+            reqwest_client,
+            story_ids,
+            title,
+            options,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
+
+    let final_path = output_path.join(format!("{}.epub", prepared.sanitized_title));
+    write_epub_file(prepared.book, story_ids[0], &final_path, options)?;
+
+    options.progress.emit(ProgressEvent::EpubSerialized {
+        bytes: std::fs::metadata(&final_path).ok().map(|m| m.len()),
+    });
+
+    info!(path = %final_path.display(), "Successfully generated omnibus EPUB file");
+    Ok(OmnibusDownload {
+        sanitized_title: prepared.sanitized_title,
+        epub_response: final_path,
+        stories: prepared.stories,
+    })
+}
+
+/// Downloads several stories into a single EPUB, returning it as bytes.
+///
+/// Every story becomes a top-level entry of the table of contents, opening with a title
+/// page (see [`crate::DownloadOptionsBuilder::title_page`]) followed by its chapters.
+/// The table of contents page, when enabled, comes first and lists every story. The chapter
+/// files and image folders of story `k` are prefixed with `s{k}_`, so the stories never
+/// collide. The book uses the cover, language and reading direction of the first story.
+///
+/// Stories are downloaded one after another; the first story that fails fails the whole
+/// omnibus. The [`crate::PartSelection`] of `options` applies to every story.
+///
+/// # Arguments
+/// * `story_ids` - The stories, in book order. Duplicates are included once.
+/// * `title` - The title of the omnibus.
+/// * `options` - The download settings, applied to every story.
+///
+/// # Returns
+/// A `Result` containing the `Vec<u8>` of the generated EPUB file.
+#[instrument(skip(wattpad_client, reqwest_client, options))]
+pub async fn download_omnibus_to_memory(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_ids: &[u64],
+    title: &str,
+    options: &DownloadOptions,
+) -> Result<OmnibusDownload<Vec<u8>>, AppError> {
+    let mut prepared = options
+        .cancellation
+        .run_until_cancelled(prepare_omnibus(
+            wattpad_client,
+            reqwest_client,
+            story_ids,
+            title,
+            options,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
+
+    let mut cursor = Cursor::new(Vec::new());
+    write_epub(&mut prepared.book, story_ids[0], &mut cursor)?;
+    ensure_not_cancelled(options)?;
+    let epub_bytes = cursor.into_inner();
+
+    options.progress.emit(ProgressEvent::EpubSerialized {
+        bytes: Some(epub_bytes.len() as u64),
+    });
+
+    info!(
+        bytes = epub_bytes.len(),
+        "Successfully generated omnibus EPUB in memory"
+    );
+    Ok(OmnibusDownload {
+        sanitized_title: prepared.sanitized_title,
+        epub_response: epub_bytes,
+        stories: prepared.stories,
+    })
+}
+
+async fn prepare_omnibus(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_ids: &[u64],
+    title: &str,
+    options: &DownloadOptions,
+) -> Result<PreparedOmnibus, AppError> {
+    let mut seen = HashSet::new();
+    let story_ids: Vec<u64> = story_ids
+        .iter()
+        .copied()
+        .filter(|story_id| seen.insert(*story_id))
+        .collect();
+    if story_ids.is_empty() {
+        return Err(AppError::OmnibusEmpty);
+    }
+
+    let mut processed_stories = Vec::with_capacity(story_ids.len());
+    for (k, &story_id) in story_ids.iter().enumerate() {
+        let file_prefix = story_prefix(k);
+        let processed = fetch_processed_story(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            &file_prefix,
+            options,
+        )
+        .await?;
+        processed_stories.push((story_id, processed));
+    }
+
+    info!(stories = story_ids.len(), "Building omnibus EPUB");
+    // The first story's cover becomes the book cover, which its title page shows as well.
+    let book_cover = processed_stories[0].1.cover.take();
+    let has_book_cover = book_cover.is_some();
+    let (_, first) = &processed_stories[0];
+    let mut authors: Vec<&str> = Vec::new();
+    for (_, processed) in &processed_stories {
+        let author = story_author(&processed.story);
+        if !authors.contains(&author) {
+            authors.push(author);
+        }
+    }
+    let titles: Vec<&str> = processed_stories
+        .iter()
+        .map(|(_, processed)| processed.title.as_str())
+        .collect();
+    let mut epub_builder = new_epub_builder(&first.story, title)
+        .with_creator(authors.join(", "))
+        .with_description(format!("An omnibus of {}.", titles.join(", ")))
+        .custome_nav(true);
+    if let Some(cover_data) = book_cover {
+        epub_builder = epub_builder.cover("cover.jpg", cover_data);
+    }
+
+    if options.toc_page {
+        let entries: Vec<TocEntry> = processed_stories
+            .iter()
+            .enumerate()
+            .map(|(k, (_, processed))| {
+                let chapters = processed
+                    .chapters
+                    .iter()
+                    .map(|chapter| {
+                        let heading = &options.chapter_heading;
+                        let title = heading.toc_title(chapter.position, &chapter.title);
+                        TocEntry::chapter(
+                            title,
+                            &chapter.file_name,
+                            &chapter.html_content,
+                            options.toc_word_counts,
+                        )
+                    })
+                    .collect();
+                TocEntry::story(&processed.title, &title_page_file(k), chapters)
+            })
+            .collect();
+        epub_builder = epub_builder
+            .add_chapter(chapter_html(
+                "Contents",
+                TOC_PAGE_FILE_NAME,
+                lang_util::get_lang_code(story_language_id(&first.story)),
+                toc_page_body(&entries),
+            ))
+            .add_nav(
+                EpubNav::default()
+                    .with_title("Contents")
+                    .with_file_name(TOC_PAGE_FILE_NAME),
+            );
+    }
+
+    let downloaded_at = now_timestamp();
+    let mut part_records = Vec::new();
+    let mut stories = Vec::with_capacity(processed_stories.len());
+    for (k, (story_id, processed)) in processed_stories.into_iter().enumerate() {
+        let ProcessedStory {
+            story,
+            title: story_title,
+            chapters,
+            cover,
+            mut report,
+        } = processed;
+        let file_prefix = story_prefix(k);
+        let language_code = lang_util::get_lang_code(story_language_id(&story));
+
+        let cover_path = match cover {
+            Some(cover_data) => {
+                let cover_path = format!("images/{}cover.jpg", file_prefix);
+                epub_builder = epub_builder.add_assets(&cover_path, cover_data);
+                Some(cover_path)
+            }
+            None if k == 0 && has_book_cover => Some("cover.jpg".to_string()),
+            None => None,
+        };
+        let title_page = title_page_file(k);
+        epub_builder = epub_builder.add_chapter(chapter_html(
+            &story_title,
+            &title_page,
+            language_code,
+            title_page_body(
+                story_id,
+                &story,
+                &story_title,
+                cover_path.as_deref(),
+                &downloaded_at,
+            ),
+        ));
+
+        let mut story_nav = EpubNav::default()
+            .with_title(story_title.as_str())
+            .with_file_name(title_page.as_str());
+        for chapter in chapters {
+            let heading = &options.chapter_heading;
+            let toc_title = heading.toc_title(chapter.position, &chapter.title);
+            story_nav.push(
+                EpubNav::default()
+                    .with_title(toc_title)
+                    .with_file_name(chapter.file_name.as_str()),
+            );
+            part_records.push(chapter.part_record());
+            epub_builder =
+                add_processed_chapter(epub_builder, story_id, chapter, language_code, &mut report);
+        }
+        epub_builder = epub_builder.add_nav(story_nav);
+
+        stories.push(OmnibusStory {
+            story_id,
+            metadata: story,
+            report,
+        });
+    }
+
+    let identifier = format!(
+        "urn:wp-mini-epub:omnibus:{}",
+        story_ids
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join("-")
+    );
+    let mut book = finish_book_from(epub_builder, identifier, &story_ids, &part_records)?;
+    add_stylesheet(&mut book, options);
+    let sanitized_title = sanitized_title(story_ids[0], &stories[0].metadata, title, options);
+    Ok(PreparedOmnibus {
+        book,
+        sanitized_title,
+        stories,
+    })
+}
+
+/// The title page of the `k`-th (0-based) story.
+fn title_page_file(k: usize) -> String {
+    format!("{}title.xhtml", story_prefix(k))
+}
+
+/// The prefix of the chapter files and image folders of the `k`-th (0-based) story.
+fn story_prefix(k: usize) -> String {
+    format!("s{}_", k + 1)
+}
@@ -0,0 +1,519 @@
+use crate::batch::SharedImages;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use crate::cache::DownloadCache;
+use crate::file_name::FileNameTemplate;
+use crate::progress::{ProgressEvent, ProgressReporter};
+use crate::rate_limit::RateLimiter;
+use crate::retry::RetryPolicy;
+use crate::selection::PartSelection;
+use crate::style::{EmbeddedFont, Theme};
+use crate::volume::VolumeSplit;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::thread;
+use tokio_util::sync::CancellationToken;
+use wp_mini::field::StoryField;
+
+/// How a download reacts to content that could not be fetched or processed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Skip failed chapters and replace failed images with a placeholder.
+    #[default]
+    Lenient,
+    /// Fail when any chapter is missing from the content ZIP or fails to process.
+    RequireAllChapters,
+    /// Like `RequireAllChapters`, but also fail when any image or the cover fails to download.
+    RequireAllContent,
+}
+
+impl Strictness {
+    pub(crate) fn requires_all_chapters(self) -> bool {
+        self != Strictness::Lenient
+    }
+
+    pub(crate) fn requires_all_images(self) -> bool {
+        self == Strictness::RequireAllContent
+    }
+}
+
+/// The heading shown at the top of every chapter.
+///
+/// Also decides how chapters are titled in the tables of contents.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ChapterHeading {
+    /// No heading. The tables of contents show the numbered part title, e.g. `3. Title`.
+    None,
+    /// The part title. The tables of contents show it numbered, e.g. `3. Title`.
+    #[default]
+    Title,
+    /// A template where `{n}` is replaced with the position of the part in the story and
+    /// `{title}` with the part title, e.g. `Chapter {n}: {title}`. The tables of contents
+    /// show the heading as is.
+    Template(String),
+}
+
+impl ChapterHeading {
+    /// The heading of the part at `position` in the story, if any.
+    pub(crate) fn heading(&self, position: usize, title: &str) -> Option<String> {
+        match self {
+            ChapterHeading::None => None,
+            ChapterHeading::Title => Some(title.to_string()),
+            ChapterHeading::Template(template) => Some(
+                template
+                    .replace("{n}", &position.to_string())
+                    .replace("{title}", title),
+            ),
+        }
+    }
+
+    /// The title of the part at `position` in the story in the tables of contents.
+    ///
+    /// Numbered by the position rather than within the book, so that volumes and part
+    /// selections keep the part numbers of the story.
+    pub(crate) fn toc_title(&self, position: usize, title: &str) -> String {
+        match self {
+            ChapterHeading::Template(_) => self.heading(position, title).unwrap_or_default(),
+            _ => format!("{}. {}", position, title),
+        }
+    }
+}
+
+/// Settings shared by every `download_story_to_*` entry point.
+///
+/// Build one with [`DownloadOptions::builder`] and reuse it across downloads;
+/// new settings are added here instead of to the function signatures.
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Whether images referenced by chapters are downloaded and embedded.
+    pub(crate) embed_images: bool,
+    /// Maximum number of chapters processed at once.
+    pub(crate) concurrent_chapters: usize,
+    /// Maximum number of image downloads in flight across the whole story.
+    pub(crate) concurrent_images: usize,
+    /// Maximum number of chapters whose HTML is parsed or rewritten at once.
+    pub(crate) concurrent_html_rewrites: usize,
+    /// Maximum number of stories a batch download processes at once.
+    pub(crate) concurrent_stories: usize,
+    /// Additional story fields to request alongside the ones the EPUB needs.
+    pub(crate) extra_fields: Vec<StoryField>,
+    /// Which parts of the story are downloaded.
+    pub(crate) part_selection: PartSelection,
+    /// How long stories are split into volumes, if at all.
+    pub(crate) volume_split: Option<VolumeSplit>,
+    /// Whether a title page describing the story precedes the first chapter.
+    pub(crate) title_page: bool,
+    /// Whether a table of contents page precedes the first chapter.
+    pub(crate) toc_page: bool,
+    /// Whether the table of contents page shows the word count of each chapter.
+    pub(crate) toc_word_counts: bool,
+    /// The heading shown at the top of every chapter.
+    pub(crate) chapter_heading: ChapterHeading,
+    /// Whether a first paragraph repeating the part title is removed.
+    pub(crate) remove_duplicate_titles: bool,
+    /// How the EPUB files are named.
+    pub(crate) file_name: FileNameTemplate,
+    /// The built-in look of the chapters, if any.
+    pub(crate) theme: Option<Theme>,
+    /// CSS added after the theme.
+    pub(crate) stylesheet: Option<String>,
+    /// Fonts embedded into the EPUB.
+    pub(crate) fonts: Vec<EmbeddedFont>,
+    /// Receives progress events while the story is downloaded.
+    pub(crate) progress: ProgressReporter,
+    /// Cancels the download when triggered.
+    pub(crate) cancellation: CancellationToken,
+    /// Whether missing chapters or images fail the download.
+    pub(crate) strictness: Strictness,
+    /// How failed HTTP requests are retried.
+    pub(crate) retry_policy: RetryPolicy,
+    /// Limits the requests of this and any other download sharing the limiter.
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    /// Where chapter content and images are cached between downloads.
+    #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+    pub(crate) cache: Option<DownloadCache>,
+    /// Images shared between the stories of a batch download. Only set by the batch.
+    pub(crate) shared_images: Option<SharedImages>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            embed_images: true,
+            concurrent_chapters: 4,
+            concurrent_images: 8,
+            concurrent_html_rewrites: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            concurrent_stories: 2,
+            extra_fields: Vec::new(),
+            part_selection: PartSelection::default(),
+            volume_split: None,
+            title_page: false,
+            toc_page: false,
+            toc_word_counts: false,
+            chapter_heading: ChapterHeading::default(),
+            remove_duplicate_titles: false,
+            file_name: FileNameTemplate::default(),
+            theme: None,
+            stylesheet: None,
+            fonts: Vec::new(),
+            progress: ProgressReporter::default(),
+            cancellation: CancellationToken::new(),
+            strictness: Strictness::default(),
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+            #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+            cache: None,
+            shared_images: None,
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// Creates a new builder, starting from the default options.
+    pub fn builder() -> DownloadOptionsBuilder {
+        DownloadOptionsBuilder::default()
+    }
+
+    /// Whether images are downloaded and embedded into the EPUB.
+    pub fn embed_images(&self) -> bool {
+        self.embed_images
+    }
+
+    /// The maximum number of chapters processed at once.
+    pub fn concurrent_chapters(&self) -> usize {
+        self.concurrent_chapters
+    }
+
+    /// The maximum number of image downloads in flight across the whole story.
+    pub fn concurrent_images(&self) -> usize {
+        self.concurrent_images
+    }
+
+    /// The maximum number of chapters whose HTML is processed at once.
+    pub fn concurrent_html_rewrites(&self) -> usize {
+        self.concurrent_html_rewrites
+    }
+
+    /// The maximum number of stories a batch download processes at once.
+    pub fn concurrent_stories(&self) -> usize {
+        self.concurrent_stories
+    }
+
+    /// The extra story fields requested on top of the required ones.
+    pub fn extra_fields(&self) -> &[StoryField] {
+        &self.extra_fields
+    }
+
+    /// Which parts of the story are downloaded.
+    pub fn part_selection(&self) -> &PartSelection {
+        &self.part_selection
+    }
+
+    /// How long stories are split into volumes, if at all.
+    pub fn volume_split(&self) -> Option<VolumeSplit> {
+        self.volume_split
+    }
+
+    /// Whether a title page describing the story precedes the first chapter.
+    pub fn title_page(&self) -> bool {
+        self.title_page
+    }
+
+    /// Whether a table of contents page precedes the first chapter.
+    pub fn toc_page(&self) -> bool {
+        self.toc_page
+    }
+
+    /// Whether the table of contents page shows the word count of each chapter.
+    pub fn toc_word_counts(&self) -> bool {
+        self.toc_word_counts
+    }
+
+    /// The heading shown at the top of every chapter.
+    pub fn chapter_heading(&self) -> &ChapterHeading {
+        &self.chapter_heading
+    }
+
+    /// Whether a first paragraph repeating the part title is removed.
+    pub fn remove_duplicate_titles(&self) -> bool {
+        self.remove_duplicate_titles
+    }
+
+    /// How the EPUB files are named.
+    pub fn file_name(&self) -> &FileNameTemplate {
+        &self.file_name
+    }
+
+    /// The built-in look of the chapters, if any.
+    pub fn theme(&self) -> Option<Theme> {
+        self.theme
+    }
+
+    /// The CSS added after the theme, if any.
+    pub fn stylesheet(&self) -> Option<&str> {
+        self.stylesheet.as_deref()
+    }
+
+    /// The fonts embedded into the EPUB.
+    pub fn fonts(&self) -> &[EmbeddedFont] {
+        &self.fonts
+    }
+
+    /// How missing chapters and images are handled.
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    /// How failed HTTP requests are retried.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// The rate limiter requests go through, if any.
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// The on-disk cache, if any.
+    ///
+    /// Excluded for wasm32
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn cache(&self) -> Option<&DownloadCache> {
+        self.cache.as_ref()
+    }
+
+    /// Runs an HTTP request through the rate limiter, if one is set.
+    pub(crate) async fn throttled<F: Future>(&self, request: F) -> F::Output {
+        match &self.rate_limiter {
+            Some(limiter) => limiter.run(request).await,
+            None => request.await,
+        }
+    }
+}
+
+/// A builder for creating [`DownloadOptions`].
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptionsBuilder {
+    options: DownloadOptions,
+}
+
+impl DownloadOptionsBuilder {
+    /// Set whether images should be downloaded and embedded. Defaults to `true`.
+    pub fn embed_images(mut self, embed_images: bool) -> Self {
+        self.options.embed_images = embed_images;
+        self
+    }
+
+    /// Set the maximum number of chapters processed at once. Defaults to `4`.
+    /// A value of `0` is treated as `1`.
+    pub fn concurrent_chapters(mut self, concurrent_chapters: usize) -> Self {
+        self.options.concurrent_chapters = concurrent_chapters.max(1);
+        self
+    }
+
+    /// Set the maximum number of image downloads in flight. Defaults to `8`.
+    /// A value of `0` is treated as `1`.
+    ///
+    /// The limit applies to the whole story rather than to each chapter, so a
+    /// chapter with many images cannot hold up the others.
+    pub fn concurrent_images(mut self, concurrent_images: usize) -> Self {
+        self.options.concurrent_images = concurrent_images.max(1);
+        self
+    }
+
+    /// Set the maximum number of chapters whose HTML is parsed or rewritten at once.
+    /// Defaults to the available parallelism. A value of `0` is treated as `1`.
+    ///
+    /// In non-WASM builds this work runs on Tokio's blocking thread pool.
+    pub fn concurrent_html_rewrites(mut self, concurrent_html_rewrites: usize) -> Self {
+        self.options.concurrent_html_rewrites = concurrent_html_rewrites.max(1);
+        self
+    }
+
+    /// Set the maximum number of stories a batch download processes at once. Defaults to `2`.
+    /// A value of `0` is treated as `1`. Ignored by single-story downloads.
+    pub fn concurrent_stories(mut self, concurrent_stories: usize) -> Self {
+        self.options.concurrent_stories = concurrent_stories.max(1);
+        self
+    }
+
+    /// Request additional story fields, returned in [`crate::StoryDownload::metadata`].
+    pub fn extra_fields(mut self, fields: &[StoryField]) -> Self {
+        self.options.extra_fields = fields.to_vec();
+        self
+    }
+
+    /// Only download the parts chosen by a [`PartSelection`]. Defaults to every part.
+    ///
+    /// The EPUB title, file name and table of contents reflect the selection, and
+    /// [`crate::DownloadReport::total_chapters`] counts the selected parts only.
+    /// Fails with [`crate::AppError::NoPartsSelected`] when nothing matches.
+    pub fn parts(mut self, selection: PartSelection) -> Self {
+        self.options.part_selection = selection;
+        self
+    }
+
+    /// Split long stories into volumes, see [`VolumeSplit`]. Disabled by default.
+    ///
+    /// Every volume is a complete EPUB titled `Title Vol. N`, carrying the story cover and
+    /// series metadata (EPUB 3 collections and Calibre's `series` / `series_index`), and
+    /// identified as `{story URL}#vol-N`. Volumes cannot be passed to
+    /// [`crate::update_story_epub`]; download the story again instead.
+    /// Only [`crate::download_story_to_folder`] splits; the other outputs ignore this.
+    pub fn split_volumes(mut self, split: VolumeSplit) -> Self {
+        self.options.volume_split = Some(split);
+        self
+    }
+
+    /// Set whether a title page precedes the first chapter. Defaults to `false`.
+    ///
+    /// The page shows the title, author, cover, description, tags, completion status,
+    /// mature flag, part count, source URL and download date, plus the read, vote and
+    /// comment counts and dates when requested through [`Self::extra_fields`].
+    /// Enabling it requests the tags, status, mature flag and part count as well.
+    pub fn title_page(mut self, title_page: bool) -> Self {
+        self.options.title_page = title_page;
+        self
+    }
+
+    /// Set whether a table of contents page precedes the first chapter. Defaults to `false`.
+    ///
+    /// Unlike the reader's own navigation, the page is part of the reading order, after
+    /// the title page. It links every chapter of the book.
+    pub fn toc_page(mut self, toc_page: bool) -> Self {
+        self.options.toc_page = toc_page;
+        self
+    }
+
+    /// Set whether the table of contents page shows the word count of each chapter,
+    /// counted like [`crate::PlannedPart::word_count`]. Defaults to `false`.
+    ///
+    /// Has no effect unless [`Self::toc_page`] is enabled.
+    pub fn toc_word_counts(mut self, toc_word_counts: bool) -> Self {
+        self.options.toc_word_counts = toc_word_counts;
+        self
+    }
+
+    /// Set the heading shown at the top of every chapter, which also titles the chapters
+    /// in the tables of contents. Defaults to [`ChapterHeading::Title`].
+    pub fn chapter_heading(mut self, chapter_heading: ChapterHeading) -> Self {
+        self.options.chapter_heading = chapter_heading;
+        self
+    }
+
+    /// Set whether the first paragraph of a chapter is removed when it only repeats the
+    /// part title, as many authors start their parts with it. Defaults to `false`.
+    ///
+    /// The comparison ignores case, punctuation, whitespace and a leading chapter number
+    /// such as `Chapter 3:`. Empty paragraphs before it are skipped.
+    pub fn remove_duplicate_titles(mut self, remove_duplicate_titles: bool) -> Self {
+        self.options.remove_duplicate_titles = remove_duplicate_titles;
+        self
+    }
+
+    /// Set how the EPUB files are named, see [`FileNameTemplate`]. Defaults to
+    /// `{id}-{title}`.
+    ///
+    /// The name is returned as the `sanitized_title` of every download, and used as the
+    /// file name by the functions writing into a folder.
+    pub fn file_name(mut self, file_name: FileNameTemplate) -> Self {
+        self.options.file_name = file_name;
+        self
+    }
+
+    /// Style the chapters with a built-in [`Theme`]. Defaults to none, leaving the look
+    /// to the reader.
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.options.theme = Some(theme);
+        self
+    }
+
+    /// Add CSS to the stylesheet every chapter links. It comes after the [`Self::theme`],
+    /// so its rules take precedence.
+    ///
+    /// Chapters wrap their content in `section[epub:type="chapter"]` under an `h1` heading.
+    pub fn stylesheet(mut self, css: impl Into<String>) -> Self {
+        self.options.stylesheet = Some(css.into());
+        self
+    }
+
+    /// Embed a font into the EPUB, declared in the stylesheet with `@font-face`.
+    /// Call it once per font file.
+    pub fn embed_font(mut self, font: EmbeddedFont) -> Self {
+        self.options.fonts.push(font);
+        self
+    }
+
+    /// Register a callback receiving [`ProgressEvent`]s during the download.
+    ///
+    /// The callback is invoked from the download tasks, so it should return quickly
+    /// (e.g. forward the event into a channel).
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(ProgressEvent) + Send + Sync + 'static,
+    {
+        self.options.progress = ProgressReporter::new(Arc::new(callback));
+        self
+    }
+
+    /// Set a token that cancels the download when triggered.
+    ///
+    /// A cancelled download stops issuing requests, returns [`crate::AppError::Cancelled`]
+    /// and leaves nothing behind at the output path.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.options.cancellation = token;
+        self
+    }
+
+    /// Set how missing chapters and images are handled. Defaults to [`Strictness::Lenient`].
+    ///
+    /// Regardless of this setting, a story without parts or without a single
+    /// successfully processed chapter always fails instead of producing an empty EPUB.
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.options.strictness = strictness;
+        self
+    }
+
+    /// Set how failed requests for metadata, content, images and the cover are retried.
+    /// Defaults to [`RetryPolicy::default`]; use [`RetryPolicy::none`] to disable retries.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.options.retry_policy = retry_policy;
+        self
+    }
+
+    /// Send every request through a [`RateLimiter`]. Unlimited by default.
+    ///
+    /// Pass clones of the same limiter to several downloads to cap their combined traffic.
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.options.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Consult a [`DownloadCache`] before fetching chapter content, images and the cover.
+    /// Disabled by default.
+    ///
+    /// Excluded for wasm32
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn cache(mut self, cache: DownloadCache) -> Self {
+        self.options.cache = Some(cache);
+        self
+    }
+
+    /// Builds the `DownloadOptions`.
+    pub fn build(self) -> DownloadOptions {
+        self.options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_toc_titles_by_part_position() {
+        assert_eq!(ChapterHeading::Title.toc_title(10, "Title"), "10. Title");
+        assert_eq!(ChapterHeading::None.toc_title(10, "Title"), "10. Title");
+        let template = ChapterHeading::Template("Chapter {n}: {title}".to_string());
+        assert_eq!(template.toc_title(10, "Title"), "Chapter 10: Title");
+    }
+}
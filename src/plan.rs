@@ -0,0 +1,195 @@
+use crate::error::AppError;
+use crate::html;
+use crate::options::DownloadOptions;
+use crate::processor::{
+    build_prepared_story, fetch_story_metadata, load_parts_content, select_parts,
+    write_epub_to_memory,
+};
+use crate::progress::ProgressEvent;
+use crate::types::StoryDownload;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::fmt;
+use tracing::{info, instrument};
+use wp_mini::WattpadClient;
+use wp_mini::types::{PartStubResponse, StoryResponse};
+
+/// Everything known about a story before it is built, returned by [`fetch_story_plan`].
+///
+/// Holds the story content as well, so [`build_from_plan`] neither repeats the
+/// metadata request nor downloads the content again.
+#[derive(Clone)]
+pub struct StoryPlan {
+    /// The Wattpad story ID.
+    pub story_id: u64,
+    /// The story metadata, including the title, description and cover URL.
+    pub metadata: StoryResponse,
+    /// Every part of the story, in story order.
+    pub parts: Vec<PlannedPart>,
+    /// The HTML of every part, keyed by part ID.
+    content: HashMap<i64, String>,
+}
+
+/// A part of a [`StoryPlan`].
+#[derive(Debug, Clone)]
+pub struct PlannedPart {
+    /// The 1-based position of the part in the story, as used by [`crate::PartSelection::range`].
+    pub position: usize,
+    /// The Wattpad part ID.
+    pub part_id: u64,
+    /// The part title.
+    pub title: String,
+    /// The number of words in the part. `None` when its content is unavailable.
+    pub word_count: Option<usize>,
+    /// When the part was created.
+    pub create_date: Option<String>,
+    /// When the part was last modified.
+    pub modify_date: Option<String>,
+    /// The number of images the part references. Images that fail to download are
+    /// still counted. `None` when its content is unavailable.
+    pub estimated_images: Option<usize>,
+}
+
+impl StoryPlan {
+    /// The number of words across all parts with available content.
+    pub fn word_count(&self) -> usize {
+        self.parts.iter().filter_map(|part| part.word_count).sum()
+    }
+
+    /// The number of images referenced across all parts with available content.
+    pub fn estimated_images(&self) -> usize {
+        self.parts
+            .iter()
+            .filter_map(|part| part.estimated_images)
+            .sum()
+    }
+}
+
+impl fmt::Debug for StoryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Leaves out the metadata and the content, which holds the HTML of every part.
+        f.debug_struct("StoryPlan")
+            .field("story_id", &self.story_id)
+            .field("parts", &self.parts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PlannedPart {
+    fn new(position: usize, part: &PartStubResponse, html: Option<&String>) -> Self {
+        Self {
+            position,
+            part_id: part.id.unwrap_or_default(),
+            title: part
+                .title
+                .clone()
+                .unwrap_or_else(|| "Untitled Chapter".to_string()),
+            word_count: html.map(|html| html::count_words(html)),
+            create_date: part.create_date.clone(),
+            modify_date: part.modify_date.clone(),
+            estimated_images: html
+                .and_then(|html| html::collect_image_urls(html).ok())
+                .map(|urls| urls.len()),
+        }
+    }
+}
+
+/// Fetches the metadata and content of a story without building an EPUB yet.
+///
+/// Use this to show the chapter list, cover and description before committing to a
+/// download, then pass the plan to [`build_from_plan`]. The [`crate::PartSelection`]
+/// of `options` is ignored here; the plan always lists every part.
+///
+/// # Arguments
+/// * `options` - The download settings, see [`DownloadOptions`].
+///
+/// # Returns
+/// A `Result` containing the [`StoryPlan`].
+#[instrument(skip(wattpad_client, options), fields(id = story_id))]
+pub async fn fetch_story_plan(
+    wattpad_client: &WattpadClient,
+    story_id: u64,
+    options: &DownloadOptions,
+) -> Result<StoryPlan, AppError> {
+    options
+        .cancellation
+        .run_until_cancelled(async {
+            let metadata = fetch_story_metadata(wattpad_client, story_id, options).await?;
+            let parts = metadata.parts.as_deref().unwrap_or_default();
+            if parts.is_empty() {
+                return Err(AppError::StoryHasNoParts { story_id });
+            }
+            // The plan lists every part, whatever the selection.
+            options.progress.emit(ProgressEvent::MetadataFetched {
+                title: metadata.title.clone().unwrap_or_default(),
+                total_chapters: parts.len(),
+            });
+
+            let all_parts: Vec<&PartStubResponse> = parts.iter().collect();
+            let content =
+                load_parts_content(wattpad_client, &metadata, story_id, &all_parts, options)
+                    .await?;
+            let parts = parts
+                .iter()
+                .enumerate()
+                .map(|(i, part)| {
+                    let html = part.id.and_then(|id| content.get(&(id as i64)));
+                    PlannedPart::new(i + 1, part, html)
+                })
+                .collect();
+
+            info!("Fetched story plan");
+            Ok(StoryPlan {
+                story_id,
+                metadata,
+                parts,
+                content,
+            })
+        })
+        .await
+        .ok_or(AppError::Cancelled)?
+}
+
+/// Builds the EPUB for a plan returned by [`fetch_story_plan`], returning it as bytes.
+///
+/// Only images and the cover are downloaded at this point. Pick the parts to include
+/// with [`crate::DownloadOptionsBuilder::parts`]; the same plan can be built several
+/// times with different selections.
+///
+/// # Arguments
+/// * `plan` - The plan to build.
+/// * `options` - The download settings, see [`DownloadOptions`].
+///
+/// # Returns
+/// A `Result` containing the `Vec<u8>` of the generated EPUB file.
+#[instrument(skip(reqwest_client, plan, options), fields(id = plan.story_id))]
+pub async fn build_from_plan(
+    reqwest_client: &Client,
+    plan: &StoryPlan,
+    options: &DownloadOptions,
+) -> Result<StoryDownload<Vec<u8>>, AppError> {
+    let story_id = plan.story_id;
+    let selected_parts = select_parts(&plan.metadata, story_id, options)?;
+    let chapter_html_map = selected_parts
+        .iter()
+        .filter_map(|(_, part)| {
+            let part_id = part.id? as i64;
+            Some((part_id, plan.content.get(&part_id)?.clone()))
+        })
+        .collect();
+
+    let prepared = options
+        .cancellation
+        .run_until_cancelled(build_prepared_story(
+            reqwest_client,
+            plan.metadata.clone(),
+            story_id,
+            selected_parts,
+            chapter_html_map,
+            options,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
+
+    write_epub_to_memory(prepared, story_id, options)
+}
@@ -1,24 +1,56 @@
 use super::{
     html, lang_util,
-    models::{ImageAsset, ProcessedChapter},
+    models::{
+        ChapterJob, ImageAsset, ImageResponse, ImageValidators, PartRecord, PreparedStory,
+        ProcessedChapter, ProcessedStory, DOWNLOADED_META_NAME, OMNIBUS_META_NAME,
+        STORY_META_NAME, VERSION_META_NAME, VOLUME_META_NAME,
+    },
 };
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use crate::cache::DownloadCache;
 use crate::error::AppError;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use crate::models::{PreparedVolume, PreparedVolumes};
+use crate::options::DownloadOptions;
+use crate::progress::ProgressEvent;
+use crate::retry::{self, retry, Retryable};
+use crate::selection::selection_title;
+use crate::style::add_stylesheet;
+use crate::title_page::{title_page_body, TITLE_PAGE_FILE_NAME};
+use crate::toc::{
+    copy_with_navigation, navigation_body, toc_page_body, TocEntry, TOC_PAGE_FILE_NAME,
+};
+use crate::report::{
+    CoverStatus, DownloadReport, FailedChapter, ImageFailure, MissingChapter, MissingReason,
+    PlaceholderImage,
+};
 use crate::types::StoryDownload;
-use anyhow::{anyhow, Result};
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use crate::volume::volume_title;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use futures::io::{AllowStdIo, AsyncWrite, AsyncWriteExt};
 use futures::stream::{self, StreamExt};
-use iepub::prelude::{EpubBuilder, EpubHtml};
-use reqwest::Client;
-use sanitize_filename::{sanitize_with_options, Options};
+use iepub::prelude::{EpubBook, EpubBuilder, EpubHtml, EpubMetaData, EpubNav, EpubWriter};
+use iepub::prelude::IError;
+use iepub::DateTimeFormater;
+use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, StatusCode};
 #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
 use std::path::PathBuf;
 use std::{
     collections::HashMap,
-    io::{Cursor, Read},
+    fmt,
+    io::{Cursor, Read, Seek, Write},
     path::Path,
+    sync::Arc,
+    time::Duration,
 };
+use tokio::sync::Semaphore;
 use tracing::{info, instrument, warn};
+use web_time::SystemTime;
 use wp_mini::field::{LanguageField, PartStubField, StoryField, UserStubField};
-use wp_mini::types::StoryResponse;
+use quick_xml::escape::escape;
+use wp_mini::types::{PartStubResponse, StoryResponse};
 use wp_mini::WattpadClient;
 use zip::ZipArchive;
 
@@ -27,46 +59,69 @@ static PLACEHOLDER_EPUB_PATH: &str = "images/placeholder.jpg";
 
 // --- PUBLIC API FUNCTIONS ---
 
-/// Downloads and processes a Wattpad story, saving the result as an EPUB file.
+/// Downloads and processes a Wattpad story, saving the result as EPUB files.
+///
+/// Unless [`crate::DownloadOptionsBuilder::split_volumes`] is set, or the story fits into
+/// a single volume, exactly one file is written.
 ///
 /// Excluded for wasm32
 ///
 /// # Arguments
-/// * `output_path` - The directory where the final `.epub` file will be saved.
+/// * `options` - The download settings, see [`DownloadOptions`].
+/// * `output_path` - The directory where the final `.epub` files will be saved.
 ///
 /// # Returns
-/// A `Result` containing the full `PathBuf` to the generated file.
+/// A `Result` containing the full `PathBuf` of every generated file, in volume order.
 #[cfg(not(target_arch = "wasm32"))]
-#[instrument(skip(reqwest_client, wattpad_client, concurrent_requests), fields(id = story_id, path = %output_path.display()))]
+#[instrument(skip(reqwest_client, wattpad_client, options), fields(id = story_id, path = %output_path.display()))]
 pub async fn download_story_to_folder(
     wattpad_client: &WattpadClient,
     reqwest_client: &Client,
     story_id: u64,
-    embed_images: bool,
-    concurrent_requests: usize,
+    options: &DownloadOptions,
     output_path: &Path,
-    extra_fields: Option<&[StoryField]>,
-) -> Result<StoryDownload<PathBuf>> {
-    let (epub_builder, sanitized_title, story_metadata) = prepare_epub_builder(
-        wattpad_client,
-        reqwest_client,
-        story_id,
-        embed_images,
-        concurrent_requests,
-        extra_fields,
-    )
-    .await?;
+) -> Result<StoryDownload<Vec<PathBuf>>, AppError> {
+    let processed = options
+        .cancellation
+        .run_until_cancelled(fetch_processed_story(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            "",
+            options,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
+    let prepared = assemble_volumes(processed, story_id, options)?;
 
-    let final_path = output_path.join(format!("{}.epub", sanitized_title));
-    epub_builder
-        .file(&final_path)
-        .map_err(|e| anyhow!("Failed to generate EPUB file: {:?}", e))?;
+    let mut final_paths = Vec::with_capacity(prepared.volumes.len());
+    let mut bytes = 0;
+    for volume in prepared.volumes {
+        let final_path = output_path.join(format!("{}.epub", volume.sanitized_title));
+        if let Err(e) = write_epub_file(volume.book, story_id, &final_path, options) {
+            // Don't leave an incomplete set of volumes behind.
+            for path in &final_paths {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        }
+        bytes += std::fs::metadata(&final_path).map_or(0, |m| m.len());
+        final_paths.push(final_path);
+    }
 
-    info!(path = %final_path.display(), "Successfully generated EPUB file");
+    options
+        .progress
+        .emit(ProgressEvent::EpubSerialized { bytes: Some(bytes) });
+
+    info!(
+        files = final_paths.len(),
+        "Successfully generated EPUB file"
+    );
     Ok(StoryDownload {
-        sanitized_title,
-        epub_response: final_path,
-        metadata: story_metadata,
+        sanitized_title: prepared.sanitized_title,
+        epub_response: final_paths,
+        metadata: prepared.metadata,
+        report: prepared.report,
     })
 }
 
@@ -75,78 +130,172 @@ pub async fn download_story_to_folder(
 /// Excluded for wasm32
 ///
 /// # Arguments
+/// * `options` - The download settings, see [`DownloadOptions`].
 /// * `output_file` - The file of the final `.epub` file.
 ///
 /// # Returns
 /// A `Result` containing the full `PathBuf` to the generated file.
 #[cfg(not(target_arch = "wasm32"))]
-#[instrument(skip(reqwest_client, wattpad_client, concurrent_requests), fields(id = story_id, path = %output_file.display()))]
+#[instrument(skip(reqwest_client, wattpad_client, options), fields(id = story_id, path = %output_file.display()))]
 pub async fn download_story_to_file(
     wattpad_client: &WattpadClient,
     reqwest_client: &Client,
     story_id: u64,
-    embed_images: bool,
-    concurrent_requests: usize,
+    options: &DownloadOptions,
     output_file: &Path,
-    extra_fields: Option<&[StoryField]>,
-) -> Result<StoryDownload<PathBuf>> {
-    let (epub_builder, sanitized_title, story_metadata) = prepare_epub_builder(
-        wattpad_client,
-        reqwest_client,
-        story_id,
-        embed_images,
-        concurrent_requests,
-        extra_fields,
-    )
-    .await?;
+) -> Result<StoryDownload<PathBuf>, AppError> {
+    let prepared = options
+        .cancellation
+        .run_until_cancelled(prepare_epub_builder(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            options,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
 
-    epub_builder
-        .file(output_file)
-        .map_err(|e| anyhow!("Failed to generate EPUB file: {:?}", e))?;
+    write_epub_file(prepared.book, story_id, output_file, options)?;
+
+    options.progress.emit(ProgressEvent::EpubSerialized {
+        bytes: std::fs::metadata(output_file).ok().map(|m| m.len()),
+    });
 
     info!(path = %output_file.display(), "Successfully generated EPUB file");
     Ok(StoryDownload {
-        sanitized_title,
+        sanitized_title: prepared.sanitized_title,
         epub_response: output_file.to_path_buf(),
-        metadata: story_metadata,
+        metadata: prepared.metadata,
+        report: prepared.report,
     })
 }
 
 /// Downloads and processes a Wattpad story, returning the EPUB as an in-memory byte vector.
 ///
+/// # Arguments
+/// * `options` - The download settings, see [`DownloadOptions`].
+///
 /// # Returns
 /// A `Result` containing the `Vec<u8>` of the generated EPUB file.
-#[instrument(skip(reqwest_client, wattpad_client, concurrent_requests), fields(id = story_id))]
+#[instrument(skip(reqwest_client, wattpad_client, options), fields(id = story_id))]
 pub async fn download_story_to_memory(
     wattpad_client: &WattpadClient,
     reqwest_client: &Client,
     story_id: u64,
-    embed_images: bool,
-    concurrent_requests: usize,
-    extra_fields: Option<&[StoryField]>,
-) -> Result<StoryDownload<Vec<u8>>> {
-    let (epub_builder, sanitized_title, story_metadata) = prepare_epub_builder(
-        wattpad_client,
-        reqwest_client,
-        story_id,
-        embed_images,
-        concurrent_requests,
-        extra_fields,
-    )
-    .await?;
+    options: &DownloadOptions,
+) -> Result<StoryDownload<Vec<u8>>, AppError> {
+    let prepared = options
+        .cancellation
+        .run_until_cancelled(prepare_epub_builder(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            options,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
 
-    let epub_bytes = epub_builder
-        .mem()
-        .map_err(|e| anyhow!("Failed to generate EPUB in memory: {:?}", e))?;
+    write_epub_to_memory(prepared, story_id, options)
+}
 
-    info!(
-        bytes = epub_bytes.len(),
-        "Successfully generated EPUB in memory"
-    );
+/// Downloads and processes a Wattpad story, streaming the EPUB into the provided writer.
+///
+/// The archive is assembled in an anonymous temporary file and copied into `writer`,
+/// so the EPUB is never buffered a second time in memory. wasm32 has no temporary
+/// files and assembles it in memory instead.
+///
+/// # Arguments
+/// * `options` - The download settings, see [`DownloadOptions`].
+/// * `writer` - Any seekable sink, e.g. a `File` or a `Cursor<Vec<u8>>`.
+///
+/// # Returns
+/// A `Result` handing the writer back once the EPUB has been written to it.
+#[instrument(skip(reqwest_client, wattpad_client, options, writer), fields(id = story_id))]
+pub async fn download_story_to_writer<W: Write + Seek>(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_id: u64,
+    options: &DownloadOptions,
+    mut writer: W,
+) -> Result<StoryDownload<W>, AppError> {
+    let mut prepared = options
+        .cancellation
+        .run_until_cancelled(prepare_epub_builder(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            options,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
+
+    let bytes = write_epub(&mut prepared.book, story_id, &mut writer)?;
+    ensure_not_cancelled(options)?;
+
+    options
+        .progress
+        .emit(ProgressEvent::EpubSerialized { bytes: Some(bytes) });
+
+    info!(bytes, "Successfully streamed EPUB to writer");
     Ok(StoryDownload {
-        sanitized_title,
-        epub_response: epub_bytes,
-        metadata: story_metadata,
+        sanitized_title: prepared.sanitized_title,
+        epub_response: writer,
+        metadata: prepared.metadata,
+        report: prepared.report,
+    })
+}
+
+/// Downloads and processes a Wattpad story, streaming the EPUB into an async writer.
+///
+/// The EPUB format needs a seekable target, so the archive is spooled to an anonymous
+/// temporary file and then copied into `writer`; it is never held in memory as a whole.
+///
+/// Excluded for wasm32
+///
+/// # Arguments
+/// * `options` - The download settings, see [`DownloadOptions`].
+/// * `writer` - Any `futures::io::AsyncWrite`, e.g. an HTTP body or an upload stream.
+///
+/// # Returns
+/// A `Result` handing the writer back once the EPUB has been written and flushed.
+#[cfg(not(target_arch = "wasm32"))]
+#[instrument(skip(reqwest_client, wattpad_client, options, writer), fields(id = story_id))]
+pub async fn download_story_to_async_writer<W: AsyncWrite + Unpin>(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_id: u64,
+    options: &DownloadOptions,
+    mut writer: W,
+) -> Result<StoryDownload<W>, AppError> {
+    let mut prepared = options
+        .cancellation
+        .run_until_cancelled(prepare_epub_builder(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            options,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
+
+    let mut spool = tempfile::tempfile()?;
+    let bytes = write_epub(&mut prepared.book, story_id, &mut spool)?;
+    ensure_not_cancelled(options)?;
+
+    spool.rewind()?;
+    futures::io::copy(AllowStdIo::new(spool), &mut writer).await?;
+    writer.flush().await?;
+
+    options
+        .progress
+        .emit(ProgressEvent::EpubSerialized { bytes: Some(bytes) });
+
+    info!(bytes, "Successfully streamed EPUB to async writer");
+    Ok(StoryDownload {
+        sanitized_title: prepared.sanitized_title,
+        epub_response: writer,
+        metadata: prepared.metadata,
+        report: prepared.report,
     })
 }
 
@@ -154,235 +303,1040 @@ pub async fn download_story_to_memory(
 
 /// Core internal function to fetch, process, and prepare an EpubBuilder instance.
 /// This function is not concerned with the final output format (file or memory).
-/// It returns the builder and a sanitized title for potential filename usage.
+/// It returns the builder, a sanitized title for potential filename usage and the download report.
 async fn prepare_epub_builder(
     wattpad_client: &WattpadClient,
     reqwest_client: &Client,
     story_id: u64,
-    embed_images: bool,
-    concurrent_requests: usize,
-    extra_fields: Option<&[StoryField]>,
-) -> Result<(EpubBuilder, String, StoryResponse)> {
+    options: &DownloadOptions,
+) -> Result<PreparedStory, AppError> {
+    let processed =
+        fetch_processed_story(wattpad_client, reqwest_client, story_id, "", options).await?;
+    assemble_story(processed, story_id, options)
+}
+
+/// Fetches a story and processes its selected parts, without building a book yet.
+///
+/// `file_prefix` is prepended to every chapter file stem, keeping the chapter files and
+/// image folders of several stories in one book apart.
+pub(crate) async fn fetch_processed_story(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_id: u64,
+    file_prefix: &str,
+    options: &DownloadOptions,
+) -> Result<ProcessedStory, AppError> {
     info!("Starting story download and processing");
 
     // --- 1. Fetch Story Info ---
+    let story = fetch_story_metadata(wattpad_client, story_id, options).await?;
+    let selected_parts = select_parts(&story, story_id, options)?;
+
+    // --- 2. Fetch Story Content, unless every part is cached ---
+    let parts: Vec<&PartStubResponse> = selected_parts.iter().map(|(_, part)| part).collect();
+    let chapter_html_map =
+        load_parts_content(wattpad_client, &story, story_id, &parts, options).await?;
+
+    process_story(
+        reqwest_client,
+        story,
+        story_id,
+        selected_parts,
+        chapter_html_map,
+        file_prefix,
+        options,
+    )
+    .await
+}
+
+/// Processes the selected parts of an already fetched story and builds the EPUB.
+///
+/// `chapter_html_map` holds the HTML of the selected parts, keyed by part ID.
+pub(crate) async fn build_prepared_story(
+    reqwest_client: &Client,
+    story: StoryResponse,
+    story_id: u64,
+    selected_parts: Vec<(usize, PartStubResponse)>,
+    chapter_html_map: HashMap<i64, String>,
+    options: &DownloadOptions,
+) -> Result<PreparedStory, AppError> {
+    let processed = process_story(
+        reqwest_client,
+        story,
+        story_id,
+        selected_parts,
+        chapter_html_map,
+        "",
+        options,
+    )
+    .await?;
+    assemble_story(processed, story_id, options)
+}
+
+/// Processes the chapters of a story and downloads its cover.
+async fn process_story(
+    reqwest_client: &Client,
+    story: StoryResponse,
+    story_id: u64,
+    selected_parts: Vec<(usize, PartStubResponse)>,
+    mut chapter_html_map: HashMap<i64, String>,
+    file_prefix: &str,
+    options: &DownloadOptions,
+) -> Result<ProcessedStory, AppError> {
+    let positions: Vec<usize> = selected_parts
+        .iter()
+        .map(|(position, _)| *position)
+        .collect();
+
+    // --- 3. Process Chapters Concurrently ---
+    let total_chapter_count = selected_parts.len(); // <-- GET THE COUNT HERE
+    info!(count = total_chapter_count, "Starting chapter processing");
+
+    // Consume `chapter_metadata` and `chapter_html_map` to get owned values.
+    let mut report = DownloadReport {
+        total_chapters: total_chapter_count,
+        ..Default::default()
+    };
+    let mut chapters_to_process = Vec::with_capacity(total_chapter_count);
+    for (position, part) in selected_parts {
+        // Use .remove() to take ownership of the String from the HashMap.
+        let html = part
+            .id
+            .and_then(|id_u64| chapter_html_map.remove(&(id_u64 as i64)));
+        match html {
+            Some(html) => {
+                let index = chapters_to_process.len() + 1;
+                let file_stem = format!("{}{}", file_prefix, position);
+                chapters_to_process.push(ChapterJob::new(index, position, file_stem, part, html));
+            }
+            None => {
+                record_missing_chapter(story_id, part, total_chapter_count, options, &mut report)?
+            }
+        }
+    }
+
+    let successfully_processed = process_chapters(
+        reqwest_client,
+        chapters_to_process,
+        total_chapter_count,
+        options,
+        &mut report,
+    )
+    .await?;
+
+    if successfully_processed.is_empty() {
+        return Err(AppError::NoChaptersProcessed {
+            story_id,
+            failed: report.failed_chapters.len() + report.missing_chapters.len(),
+        });
+    }
+
+    report.included_chapters = successfully_processed.len();
+    info!(
+        success_count = successfully_processed.len(),
+        total_count = total_chapter_count,
+        "Finished chapter processing"
+    );
+
+    let title = epub_title(&story, &positions);
+    let cover = fetch_cover(reqwest_client, &story, story_id, options, &mut report).await?;
+    Ok(ProcessedStory {
+        story,
+        title,
+        chapters: successfully_processed,
+        cover,
+        report,
+    })
+}
+
+/// Builds the EPUB of a single processed story.
+fn assemble_story(
+    processed: ProcessedStory,
+    story_id: u64,
+    options: &DownloadOptions,
+) -> Result<PreparedStory, AppError> {
+    let ProcessedStory {
+        story,
+        title,
+        chapters,
+        cover,
+        mut report,
+    } = processed;
+
+    let book = assemble_book(
+        &story,
+        story_id,
+        &title,
+        chapters,
+        cover,
+        None,
+        options,
+        &mut report,
+    )?;
+    Ok(PreparedStory {
+        book,
+        sanitized_title: sanitized_title(story_id, &story, &title, options),
+        metadata: story,
+        report,
+    })
+}
+
+/// Builds one EPUB per volume of a processed story, see [`crate::VolumeSplit`].
+///
+/// A story that fits into a single volume is built like any other story.
+#[cfg(not(target_arch = "wasm32"))]
+fn assemble_volumes(
+    processed: ProcessedStory,
+    story_id: u64,
+    options: &DownloadOptions,
+) -> Result<PreparedVolumes, AppError> {
+    let ProcessedStory {
+        story,
+        title,
+        chapters,
+        cover,
+        mut report,
+    } = processed;
+
+    let chapter_groups = match options.volume_split {
+        Some(split) => split.split(chapters),
+        None => vec![chapters],
+    };
+    let volume_count = chapter_groups.len();
+    if volume_count > 1 {
+        info!(volumes = volume_count, "Splitting story into volumes");
+    }
+
+    let mut volumes: Vec<PreparedVolume> = Vec::with_capacity(volume_count);
+    for (i, chapters) in chapter_groups.into_iter().enumerate() {
+        let (book_title, series) = if volume_count > 1 {
+            (volume_title(&title, i + 1), Some((title.as_str(), i + 1)))
+        } else {
+            (title.clone(), None)
+        };
+        let file_name = options.file_name.render(
+            story_id,
+            &story,
+            &title,
+            series.map(|(_, position)| position),
+        );
+        // Checked case-insensitively, as on the file systems of Windows and macOS.
+        if volumes
+            .iter()
+            .any(|volume| volume.sanitized_title.to_lowercase() == file_name.to_lowercase())
+        {
+            return Err(AppError::FileNameCollision { file_name });
+        }
+        let book = assemble_book(
+            &story,
+            story_id,
+            &book_title,
+            chapters,
+            cover.clone(),
+            series,
+            options,
+            &mut report,
+        )?;
+        volumes.push(PreparedVolume {
+            book,
+            sanitized_title: file_name,
+        });
+    }
+
+    Ok(PreparedVolumes {
+        volumes,
+        sanitized_title: sanitized_title(story_id, &story, &title, options),
+        metadata: story,
+        report,
+    })
+}
+
+/// Builds a book out of processed chapters of a story.
+///
+/// `series` names the series and the 1-based position of the book when it is a volume.
+#[allow(clippy::too_many_arguments)]
+fn assemble_book(
+    story: &StoryResponse,
+    story_id: u64,
+    title: &str,
+    chapters: Vec<ProcessedChapter>,
+    cover: Option<Vec<u8>>,
+    series: Option<(&str, usize)>,
+    options: &DownloadOptions,
+    report: &mut DownloadReport,
+) -> Result<EpubBook, AppError> {
+    // --- 4. Build EPUB ---
+    let mut epub_builder = new_epub_builder(story, title).custome_nav(true);
+    let cover_path = cover.as_ref().map(|_| "cover.jpg");
+    if let Some(cover_data) = cover {
+        epub_builder = epub_builder.cover("cover.jpg", cover_data);
+    }
+
+    let language_code = lang_util::get_lang_code(story_language_id(story));
+    let toc_entries: Vec<TocEntry> = chapters
+        .iter()
+        .map(|chapter| {
+            let heading = &options.chapter_heading;
+            let title = heading.toc_title(chapter.position, &chapter.title);
+            TocEntry::chapter(
+                title,
+                &chapter.file_name,
+                &chapter.html_content,
+                options.toc_word_counts,
+            )
+        })
+        .collect();
+    epub_builder = add_front_matter(
+        epub_builder,
+        story_id,
+        story,
+        title,
+        cover_path,
+        &toc_entries,
+        options,
+    );
+
+    let mut part_records = Vec::with_capacity(chapters.len());
+    for chapter in chapters {
+        let toc_title = options.chapter_heading.toc_title(chapter.position, &chapter.title);
+        epub_builder = epub_builder.add_nav(
+            EpubNav::default()
+                .with_title(toc_title)
+                .with_file_name(chapter.file_name.as_str()),
+        );
+        part_records.push(chapter.part_record());
+        epub_builder =
+            add_processed_chapter(epub_builder, story_id, chapter, language_code, report);
+    }
+
+    // Every volume is a book of its own, so readers must not take them for one another.
+    let identifier = match series {
+        Some((_, position)) => format!("{}#vol-{}", story_url(story_id), position),
+        None => story_url(story_id),
+    };
+    let mut book = finish_book_from(epub_builder, identifier, &[story_id], &part_records)?;
+    add_stylesheet(&mut book, options);
+    if let Some((series_title, position)) = series {
+        add_series_meta(&mut book, series_title, position);
+    }
+    Ok(book)
+}
+
+// --- PRIVATE HELPER FUNCTIONS ---
+
+/// Fetches the story metadata, including every field the EPUB needs.
+pub(crate) async fn fetch_story_metadata(
+    wattpad_client: &WattpadClient,
+    story_id: u64,
+    options: &DownloadOptions,
+) -> Result<StoryResponse, AppError> {
     let mut story_fields: Vec<StoryField> = vec![
         StoryField::Title,
         StoryField::Description,
         StoryField::Cover,
         StoryField::Language(vec![LanguageField::Id]),
         StoryField::User(vec![UserStubField::Username]),
-        StoryField::Parts(vec![PartStubField::Id, PartStubField::Title]),
+        StoryField::Parts(vec![
+            PartStubField::Id,
+            PartStubField::Title,
+            PartStubField::CreateDate,
+            PartStubField::ModifyDate,
+        ]),
     ];
 
-    if let Some(fields) = extra_fields {
-        story_fields.extend_from_slice(fields);
+    if options.file_name.needs_status() {
+        story_fields.push(StoryField::Completed);
     }
+    if options.title_page {
+        story_fields.extend([
+            StoryField::Tags,
+            StoryField::Completed,
+            StoryField::Mature,
+            StoryField::NumParts,
+        ]);
+    }
+    story_fields.extend_from_slice(&options.extra_fields);
+
+    // Merge extra part fields into the required ones, as `parts` may only be requested once.
+    let mut part_fields = Vec::new();
+    story_fields.retain(|field| match field {
+        StoryField::Parts(fields) => {
+            part_fields.extend_from_slice(fields);
+            false
+        }
+        _ => true,
+    });
+    part_fields.sort();
+    part_fields.dedup();
+    story_fields.push(StoryField::Parts(part_fields));
 
     // Remove duplicates (I guess this's not needed, though)
     story_fields.sort();
     story_fields.dedup();
 
-    let story = wattpad_client
-        .story
-        .get_story_info(story_id, Some(&story_fields))
-        .await
-        .map_err(|_| AppError::MetadataFetchFailed)?;
+    let story = retry(&options.retry_policy, || {
+        options.throttled(
+            wattpad_client
+                .story
+                .get_story_info(story_id, Some(&story_fields)),
+        )
+    })
+    .await
+    .map_err(|source| {
+        AppError::from_story_request(story_id, source, |story_id, source| {
+            AppError::MetadataFetchFailed { story_id, source }
+        })
+    })?;
 
     info!(title = ?story.title, "Successfully fetched story metadata");
+    Ok(story)
+}
 
-    // --- 2. Fetch Story Content as a ZIP ---
-    let zip_bytes = wattpad_client
-        .story
-        .get_story_content_zip(story_id)
-        .await
-        .map_err(|_| AppError::DownloadFailed)?;
+/// Returns the parts chosen by the download's [`crate::PartSelection`], paired with their
+/// 1-based position in the story.
+pub(crate) fn select_parts(
+    story: &StoryResponse,
+    story_id: u64,
+    options: &DownloadOptions,
+) -> Result<Vec<(usize, PartStubResponse)>, AppError> {
+    let parts = story.parts.clone().ok_or(AppError::MetadataIncomplete {
+        story_id,
+        field: "parts",
+    })?;
+    if parts.is_empty() {
+        return Err(AppError::StoryHasNoParts { story_id });
+    }
 
-    info!("Successfully downloaded story content ZIP");
+    let selected = options.part_selection.apply(parts);
+    if selected.is_empty() {
+        return Err(AppError::NoPartsSelected { story_id });
+    }
 
-    // --- 3. Process ZIP in Memory ---
-    let mut chapter_html_map: HashMap<i64, String> = HashMap::new();
-    let zip_cursor = Cursor::new(zip_bytes);
-    let mut archive = ZipArchive::new(zip_cursor)?;
+    // Emitted once the selection is known, so the total matches the chapter events.
+    options.progress.emit(ProgressEvent::MetadataFetched {
+        title: story.title.clone().unwrap_or_default(),
+        total_chapters: selected.len(),
+    });
+    Ok(selected)
+}
 
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let file_name = match Path::new(file.name()).file_name() {
-            Some(name) => name.to_string_lossy().into_owned(),
-            None => continue,
-        };
+/// Returns the HTML of the given parts, keyed by part ID.
+///
+/// The content ZIP is only used when all parts of the story are needed; otherwise
+/// the parts are fetched one by one.
+pub(crate) async fn load_parts_content(
+    wattpad_client: &WattpadClient,
+    story: &StoryResponse,
+    story_id: u64,
+    parts: &[&PartStubResponse],
+    options: &DownloadOptions,
+) -> Result<HashMap<i64, String>, AppError> {
+    if parts.len() == story.parts.as_ref().map_or(0, Vec::len) {
+        return load_story_content(wattpad_client, story, story_id, options).await;
+    }
 
-        if let Ok(part_id) = file_name.parse::<i64>() {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
-            chapter_html_map.insert(part_id, contents);
-        }
+    let mut fetches = stream::iter(parts)
+        .map(|part| async move {
+            let html = fetch_part_content(wattpad_client, story_id, part, options).await;
+            (part.id.unwrap_or_default() as i64, html)
+        })
+        .buffer_unordered(options.concurrent_chapters);
+
+    let mut html_map = HashMap::new();
+    while let Some((part_id, html)) = fetches.next().await {
+        html_map.insert(part_id, html?);
     }
+    let bytes = html_map.values().map(String::len).sum();
+    options
+        .progress
+        .emit(ProgressEvent::ContentDownloaded { bytes });
+    Ok(html_map)
+}
 
-    // --- 4. Process Chapters Concurrently ---
-    let chapter_metadata = story.parts.clone().ok_or(AppError::MetadataFetchFailed)?;
-    let total_chapter_count = chapter_metadata.len(); // <-- GET THE COUNT HERE
-    info!(count = total_chapter_count, "Starting chapter processing");
+/// Returns the HTML of every part, keyed by part ID, from the cache or the content ZIP.
+async fn load_story_content(
+    wattpad_client: &WattpadClient,
+    story: &StoryResponse,
+    story_id: u64,
+    options: &DownloadOptions,
+) -> Result<HashMap<i64, String>, AppError> {
+    #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+    let parts = story.parts.as_deref().unwrap_or_default();
+    #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+    let cached_html_map = options
+        .cache
+        .as_ref()
+        .and_then(|cache| cache.load_parts(story_id, parts));
+    #[cfg(target_arch = "wasm32")]
+    let cached_html_map = {
+        let _ = story;
+        None
+    };
 
-    // Consume `chapter_metadata` and `chapter_html_map` to get owned values.
-    let chapters_to_process = chapter_metadata.into_iter().filter_map(|part| {
-        // chapter_metadata is moved here
-        part.id.and_then(|id_u64| {
-            let id_i64 = id_u64 as i64;
-            // Use .remove() to take ownership of the String from the HashMap.
-            chapter_html_map.remove(&id_i64).map(|html| (part, html))
+    if let Some(html_map) = cached_html_map {
+        info!("Loaded story content from the cache");
+        let parts = html_map.len();
+        options
+            .progress
+            .emit(ProgressEvent::ContentLoadedFromCache { parts });
+        return Ok(html_map);
+    }
+
+    let html_map = fetch_story_content(wattpad_client, story_id, options).await?;
+    #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+    if let Some(cache) = &options.cache {
+        cache.store_parts(story_id, parts, &html_map);
+    }
+    Ok(html_map)
+}
+
+/// Fetches the HTML of a single part, consulting the cache first.
+async fn fetch_part_content(
+    wattpad_client: &WattpadClient,
+    story_id: u64,
+    part: &PartStubResponse,
+    options: &DownloadOptions,
+) -> Result<String, AppError> {
+    #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+    if let Some(html) = options
+        .cache
+        .as_ref()
+        .and_then(|cache| cache.load_part(story_id, part))
+    {
+        return Ok(html);
+    }
+
+    let part_id = part.id.unwrap_or_default();
+    let html = retry(&options.retry_policy, || {
+        options.throttled(wattpad_client.story.get_part_content_raw(part_id))
+    })
+    .await
+    .map_err(|source| {
+        AppError::from_story_request(story_id, source, |story_id, source| {
+            AppError::DownloadFailed { story_id, source }
         })
+    })?;
+
+    #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+    if let Some(cache) = &options.cache {
+        cache.store_part(story_id, part, &html);
+    }
+    Ok(html)
+}
+
+/// Records a part without content, or without an ID to look its content up by, or fails
+/// when every chapter is required.
+pub(crate) fn record_missing_chapter(
+    story_id: u64,
+    part: PartStubResponse,
+    total_chapter_count: usize,
+    options: &DownloadOptions,
+    report: &mut DownloadReport,
+) -> Result<(), AppError> {
+    let (part_id, reason) = match part.id {
+        Some(part_id) => (part_id, MissingReason::NotInContent),
+        None => (0, MissingReason::NoPartId),
+    };
+    if options.strictness.requires_all_chapters() {
+        return Err(AppError::ChapterMissing {
+            story_id,
+            part_id,
+            reason,
+        });
+    }
+    warn!(part_id, %reason, "Chapter content missing");
+    options.progress.emit(ProgressEvent::ChapterMissing {
+        part_id,
+        total: total_chapter_count,
+    });
+    report.missing_chapters.push(MissingChapter {
+        part_id,
+        title: part.title.unwrap_or_default(),
+        reason,
     });
+    Ok(())
+}
 
-    let processed_chapters_results: Vec<Result<ProcessedChapter>> =
-        stream::iter(chapters_to_process.enumerate())
-            .map(|(i, (metadata, html_content))| async move {
-                // `metadata` is owned, `html_content` is owned
-                process_chapter(
-                    reqwest_client,
-                    i + 1,
-                    metadata.title.as_deref().unwrap_or("Untitled Chapter"),
-                    &html_content,
-                    embed_images,
-                    concurrent_requests,
-                )
-                .await
-            })
-            .buffer_unordered(concurrent_requests)
-            .collect()
-            .await;
+/// Processes chapters concurrently, returning the successful ones sorted by index.
+///
+/// Failed chapters are added to the report, unless the strictness turns them into an error.
+pub(crate) async fn process_chapters(
+    reqwest_client: &Client,
+    chapters: Vec<ChapterJob>,
+    total_chapter_count: usize,
+    options: &DownloadOptions,
+    report: &mut DownloadReport,
+) -> Result<Vec<ProcessedChapter>, AppError> {
+    let limits = &StoryLimits::new(options);
+    let mut processed_chapters_results = stream::iter(chapters)
+        .map(|job| async move {
+            let (index, part_id, title) = (job.index, job.part_id, job.title.clone());
+            if let Err(error) = ensure_not_cancelled(options) {
+                return (index, part_id, title, Err(error));
+            }
+            options.progress.emit(ProgressEvent::ChapterStarted {
+                index,
+                total: total_chapter_count,
+                title: title.clone(),
+            });
 
+            let result = process_chapter(reqwest_client, job, options, limits).await;
+
+            options.progress.emit(match &result {
+                Ok(_) => ProgressEvent::ChapterFinished {
+                    index,
+                    total: total_chapter_count,
+                },
+                Err(_) => ProgressEvent::ChapterFailed {
+                    index,
+                    total: total_chapter_count,
+                },
+            });
+            (index, part_id, title, result)
+        })
+        .buffer_unordered(options.concurrent_chapters);
+
+    // Results are consumed as they complete, so strict downloads stop at the first failure.
     let mut successfully_processed: Vec<ProcessedChapter> = Vec::new();
-    for result in processed_chapters_results {
+    while let Some((index, part_id, title, result)) = processed_chapters_results.next().await {
         match result {
             Ok(chapter) => successfully_processed.push(chapter),
-            Err(e) => warn!("Failed to process a chapter: {}", e),
+            Err(e @ AppError::Cancelled) => return Err(e),
+            Err(e) if options.strictness.requires_all_chapters() => return Err(e),
+            Err(e) => {
+                warn!("Failed to process a chapter: {}", e);
+                report.failed_chapters.push(FailedChapter {
+                    index,
+                    part_id,
+                    title,
+                    error: e,
+                });
+            }
         }
     }
 
     successfully_processed.sort_by_key(|c| c.index);
-    info!(
-        success_count = successfully_processed.len(),
-        total_count = total_chapter_count,
-        "Finished chapter processing"
-    );
-
-    // --- 5. Build EPUB ---
-    let author = story
-        .user
-        .as_ref()
-        .and_then(|u| u.username.as_deref())
-        .unwrap_or("Unknown Author");
+    report.failed_chapters.sort_by_key(|c| c.index);
+    Ok(successfully_processed)
+}
 
+/// The title of the EPUB, naming the selected parts unless all of them were selected.
+pub(crate) fn epub_title(story: &StoryResponse, positions: &[usize]) -> String {
     let story_title = story.title.as_deref().unwrap_or("Untitled Story");
-    let story_description = story.description.as_deref().unwrap_or("");
+    let total = story.parts.as_ref().map_or(0, Vec::len);
+    selection_title(story_title, positions, total)
+}
 
-    let language_id = story
-        .language
-        .as_ref() // Safely get an Option<&Language>
-        .and_then(|lang| lang.id) // Chain to get the inner Option<u64>
-        .unwrap_or(1); // Provide a default if any part of the chain was None
+/// Adds the front matter of a single-story book that `options` ask for: its title page,
+/// showing the cover at `cover_path` when the book has one, and its table of contents
+/// page listing `toc_entries`.
+pub(crate) fn add_front_matter(
+    mut epub_builder: EpubBuilder,
+    story_id: u64,
+    story: &StoryResponse,
+    title: &str,
+    cover_path: Option<&str>,
+    toc_entries: &[TocEntry],
+    options: &DownloadOptions,
+) -> EpubBuilder {
+    let language_code = lang_util::get_lang_code(story_language_id(story));
+    if options.title_page {
+        let body = title_page_body(story_id, story, title, cover_path, &now_timestamp());
+        epub_builder = epub_builder
+            .add_chapter(chapter_html(
+                title,
+                TITLE_PAGE_FILE_NAME,
+                language_code,
+                body,
+            ))
+            .add_nav(
+                EpubNav::default()
+                    .with_title("Title Page")
+                    .with_file_name(TITLE_PAGE_FILE_NAME),
+            );
+    }
+    if options.toc_page {
+        epub_builder = epub_builder
+            .add_chapter(chapter_html(
+                "Contents",
+                TOC_PAGE_FILE_NAME,
+                language_code,
+                toc_page_body(toc_entries),
+            ))
+            .add_nav(
+                EpubNav::default()
+                    .with_title("Contents")
+                    .with_file_name(TOC_PAGE_FILE_NAME),
+            );
+    }
+    epub_builder
+}
+
+/// Creates a builder carrying the story metadata and the placeholder image.
+pub(crate) fn new_epub_builder(story: &StoryResponse, story_title: &str) -> EpubBuilder {
+    let author = story_author(story);
 
-    let language_code = lang_util::get_lang_code(language_id);
-    let language_dir = lang_util::get_direction_for_lang_id(language_id);
+    let story_description = story.description.as_deref().unwrap_or("");
+    let language_dir = lang_util::get_direction_for_lang_id(story_language_id(story));
 
     info!(author, title = story_title, "Building EPUB file");
 
-    let mut epub_builder = EpubBuilder::default()
+    EpubBuilder::default()
+        // EPUB 3, which the landmarks, series and `dcterms:source` metadata rely on.
+        .with_version("3.0")
         .with_title(story_title)
         .with_creator(author)
         .with_description(story_description)
         .with_direction(language_dir)
-        .add_assets(PLACEHOLDER_EPUB_PATH, PLACEHOLDER_IMAGE_DATA.to_vec());
+        .add_assets(PLACEHOLDER_EPUB_PATH, PLACEHOLDER_IMAGE_DATA.to_vec())
+}
+
+pub(crate) fn story_author(story: &StoryResponse) -> &str {
+    story
+        .user
+        .as_ref()
+        .and_then(|u| u.username.as_deref())
+        .unwrap_or("Unknown Author")
+}
 
-    if let Some(cover_url) = story.cover.as_deref() {
-        // Create a new String with the "-256-" part replaced
-        let high_res_url = cover_url.replace("-256-", "-512-");
+pub(crate) fn story_language_id(story: &StoryResponse) -> u64 {
+    story
+        .language
+        .as_ref() // Safely get an Option<&Language>
+        .and_then(|lang| lang.id) // Chain to get the inner Option<u64>
+        .unwrap_or(1) // Provide a default if any part of the chain was None
+}
+
+/// Downloads the high resolution cover, recording the outcome in the report.
+pub(crate) async fn fetch_cover(
+    reqwest_client: &Client,
+    story: &StoryResponse,
+    story_id: u64,
+    options: &DownloadOptions,
+    report: &mut DownloadReport,
+) -> Result<Option<Vec<u8>>, AppError> {
+    let Some(cover_url) = story.cover.as_deref() else {
+        return Ok(None);
+    };
 
-        // Pass a reference to the new high-res URL string
-        if let Ok(Some(cover_data)) = download_image(reqwest_client, &high_res_url).await {
+    // Create a new String with the "-256-" part replaced
+    let high_res_url = cover_url.replace("-256-", "-512-");
+
+    // Pass a reference to the new high-res URL string
+    match download_image(reqwest_client, &high_res_url, options).await {
+        Ok(cover_data) => {
             info!("Adding cover image to EPUB");
-            epub_builder = epub_builder.cover("cover.jpg", cover_data);
+            options.progress.emit(ProgressEvent::CoverFetched {
+                bytes: cover_data.len(),
+            });
+            report.cover = CoverStatus::Embedded;
+            Ok(Some(cover_data))
+        }
+        Err(source) if options.strictness.requires_all_images() => {
+            Err(AppError::CoverDownloadFailed { story_id, source })
+        }
+        Err(reason) => {
+            report.cover = CoverStatus::Failed(reason);
+            Ok(None)
         }
     }
+}
 
-    for chapter in successfully_processed {
-        for image in chapter.images {
-            epub_builder = epub_builder.add_assets(&image.epub_path, image.data);
-        }
-        epub_builder = epub_builder.add_chapter(
-            EpubHtml::default()
-                .with_title(&chapter.title)
-                .with_file_name(&chapter.file_name)
-                .with_language(language_code)
-                .with_data(chapter.html_content.as_bytes().to_vec()),
-        );
+/// Adds a processed chapter and its images to the builder.
+pub(crate) fn add_processed_chapter(
+    epub_builder: EpubBuilder,
+    story_id: u64,
+    chapter: ProcessedChapter,
+    language_code: &str,
+    report: &mut DownloadReport,
+) -> EpubBuilder {
+    let section = chapter_section(story_id, &chapter.part_record());
+    report.total_images += chapter.image_count;
+    report.embedded_images += chapter.images.len();
+    for (url, reason) in chapter.failed_images {
+        report.placeholder_images.push(PlaceholderImage {
+            chapter_index: chapter.index,
+            url,
+            reason,
+        });
+    }
+
+    let mut epub_builder = epub_builder;
+    for image in chapter.images {
+        epub_builder = epub_builder.add_assets(&image.epub_path, image.data);
     }
 
-    let sanitized_title = format!(
-        "{}-{}",
+    let body = format!("{}\n{}\n</section>", section, chapter.html_content);
+    epub_builder.add_chapter(chapter_html(
+        &chapter.title,
+        &chapter.file_name,
+        language_code,
+        body,
+    ))
+}
+
+pub(crate) fn chapter_html(
+    title: &str,
+    file_name: &str,
+    language_code: &str,
+    body: String,
+) -> EpubHtml {
+    EpubHtml::default()
+        .with_title(title)
+        .with_file_name(file_name)
+        .with_language(language_code)
+        .with_data(body.into_bytes())
+}
+
+/// The heading shown at the top of a chapter, see [`crate::ChapterHeading`].
+fn chapter_heading(heading: &str) -> String {
+    format!(r#"<h1 style="text-align: center">{}</h1>"#, escape(heading))
+}
+
+/// Opens the section wrapping a chapter, which records where its content came from.
+fn chapter_section(story_id: u64, record: &PartRecord) -> String {
+    let mut section = format!(
+        r#"<section epub:type="chapter" data-story-id="{}" data-part-id="{}" data-source="{}""#,
         story_id,
-        sanitize_with_options(
-            story_title,
-            Options {
-                replacement: "_",     // Set the replacement to an underscore
-                ..Default::default() // Use default values for other options like `windows` and `truncate`
-            }
-        )
+        record.part_id,
+        part_url(record.part_id)
+    );
+    if let Some(modify_date) = &record.modify_date {
+        section.push_str(&format!(r#" data-part-modified="{}""#, escape(modify_date)));
+    }
+    if let Some(content_hash) = &record.content_hash {
+        section.push_str(&format!(r#" data-content-hash="{}""#, content_hash));
+    }
+    section.push_str(&format!(r#" data-generator="{}">"#, generator()));
+    section
+}
+
+/// Builds the book, recording where it came from so it can be identified and updated later.
+pub(crate) fn finish_book(
+    epub_builder: EpubBuilder,
+    story_id: u64,
+    part_records: &[PartRecord],
+) -> Result<EpubBook, AppError> {
+    finish_book_from(epub_builder, story_url(story_id), &[story_id], part_records)
+}
+
+/// Builds a book made of one or more stories, recording where they came from.
+///
+/// Only single-story books get the story meta that [`crate::update_story_epub`] looks for;
+/// books of several stories list them in the omnibus meta instead.
+pub(crate) fn finish_book_from(
+    epub_builder: EpubBuilder,
+    identifier: String,
+    story_ids: &[u64],
+    part_records: &[PartRecord],
+) -> Result<EpubBook, AppError> {
+    let first_story_id = story_ids.first().copied().unwrap_or_default();
+    let timestamp = now_timestamp();
+
+    let mut book = epub_builder
+        .with_identifier(identifier)
+        // Set explicitly, as iepub's own timestamp is not available on wasm32.
+        .with_last_modify(&timestamp)
+        .book()
+        .map_err(|source| AppError::EpubGenerationFailed {
+            story_id: first_story_id,
+            source,
+        })?;
+    for story_id in story_ids {
+        book.add_meta(
+            EpubMetaData::default()
+                .with_attr("property", "dcterms:source")
+                .with_text(story_url(*story_id)),
+        );
+    }
+    let story_meta = match story_ids {
+        [story_id] => (STORY_META_NAME, story_id.to_string()),
+        story_ids => (
+            OMNIBUS_META_NAME,
+            story_ids
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+    };
+    for (name, content) in [
+        story_meta,
+        (DOWNLOADED_META_NAME, timestamp),
+        (VERSION_META_NAME, env!("CARGO_PKG_VERSION").to_string()),
+    ] {
+        book.add_meta(
+            EpubMetaData::default()
+                .with_attr("name", name)
+                .with_attr("content", &content),
+        );
+    }
+    for record in part_records {
+        book.add_meta(record.to_meta());
+    }
+    Ok(book)
+}
+
+/// Marks a book as the `position`-th volume of a series, for EPUB 3 readers and Calibre,
+/// and records the volume so [`crate::update_story_epub`] can tell it from a whole story.
+fn add_series_meta(book: &mut EpubBook, series_title: &str, position: usize) {
+    let position = position.to_string();
+    book.add_meta(
+        EpubMetaData::default()
+            .with_attr("property", "belongs-to-collection")
+            .with_attr("id", "series")
+            .with_text(series_title),
     );
+    for (property, value) in [("collection-type", "series"), ("group-position", &position)] {
+        book.add_meta(
+            EpubMetaData::default()
+                .with_attr("refines", "#series")
+                .with_attr("property", property)
+                .with_text(value),
+        );
+    }
+    for (name, content) in [
+        ("calibre:series", series_title),
+        ("calibre:series_index", &position),
+        (VOLUME_META_NAME, &position),
+    ] {
+        book.add_meta(
+            EpubMetaData::default()
+                .with_attr("name", name)
+                .with_attr("content", content),
+        );
+    }
+}
 
-    Ok((epub_builder, sanitized_title, story))
+pub(crate) fn story_url(story_id: u64) -> String {
+    format!("https://www.wattpad.com/story/{}", story_id)
 }
 
-// --- PRIVATE HELPER FUNCTIONS ---
+fn part_url(part_id: u64) -> String {
+    format!("https://www.wattpad.com/{}", part_id)
+}
+
+/// The current time in the format of the OPF `dcterms:modified` meta.
+pub(crate) fn now_timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    DateTimeFormater::new(now).default_format()
+}
+
+pub(crate) fn generator() -> String {
+    format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// The file name of a book, without the extension, see [`crate::FileNameTemplate`].
+pub(crate) fn sanitized_title(
+    story_id: u64,
+    story: &StoryResponse,
+    title: &str,
+    options: &DownloadOptions,
+) -> String {
+    options.file_name.render(story_id, story, title, None)
+}
 
-#[instrument(skip(reqwest_client, html_in), fields(index, title))]
+#[instrument(skip(reqwest_client, job, options, limits), fields(index = job.index, title = %job.title))]
 async fn process_chapter(
     reqwest_client: &Client,
-    index: usize,
-    title: &str,
-    html_in: &str,
-    embed_images: bool,
-    concurrent_requests: usize,
-) -> Result<ProcessedChapter> {
+    job: ChapterJob,
+    options: &DownloadOptions,
+    limits: &StoryLimits,
+) -> Result<ProcessedChapter, AppError> {
+    let ChapterJob {
+        index,
+        position,
+        part_id,
+        title,
+        modify_date,
+        file_stem,
+        html: html_in,
+        content_hash,
+    } = job;
+    let chapter_error = |source| AppError::ChapterProcessingFailed {
+        part_id,
+        index,
+        source,
+    };
     let mut images = Vec::new();
-    let image_map = if embed_images {
-        let image_urls = html::collect_image_urls(html_in)?;
+    let mut failed_images = Vec::new();
+    let mut image_count = 0;
+    let html_in: Arc<str> = html_in.into();
+    let image_map = if options.embed_images {
+        let html = Arc::clone(&html_in);
+        let image_urls = limits
+            .run_html_task(move || html::collect_image_urls(&html))
+            .await
+            .map_err(chapter_error)?;
+        image_count = image_urls.len();
+        options.progress.emit(ProgressEvent::ChapterImagesFound {
+            index,
+            count: image_urls.len(),
+        });
 
-        let image_download_futures = stream::iter(image_urls)
+        let mut image_download_futures = stream::iter(image_urls)
             .map(|url| async move {
-                let download_result = download_image(reqwest_client, &url).await.unwrap_or(None);
+                if options.cancellation.is_cancelled() {
+                    return (url, Err(ImageFailure::Cancelled));
+                }
+                let download_result = {
+                    let _permit = limits.images.acquire().await.expect("semaphore closed");
+                    download_image(reqwest_client, &url, options).await
+                };
+                options.progress.emit(match &download_result {
+                    Ok(data) => ProgressEvent::ImageDownloaded {
+                        index,
+                        url: url.clone(),
+                        bytes: data.len(),
+                    },
+                    Err(_) => ProgressEvent::ImagePlaceholdered {
+                        index,
+                        url: url.clone(),
+                    },
+                });
                 (url, download_result)
             })
-            .buffer_unordered(concurrent_requests)
-            .collect::<Vec<(String, Option<Vec<u8>>)>>()
-            .await;
+            .buffer_unordered(options.concurrent_images);
 
         let mut map = HashMap::new();
         let mut successful_image_index = 0;
-        for (original_url, data_option) in image_download_futures {
-            if let Some(data) = data_option {
-                // --- SUCCESSFUL DOWNLOAD ---
-                let extension = html::infer_extension_from_data(&data).unwrap_or("jpg");
-                let epub_path = format!(
-                    "images/chapter_{}/image_{}.{}",
-                    index, successful_image_index, extension
-                );
-
-                // Add the new asset to be bundled with the chapter
-                images.push(ImageAsset {
-                    epub_path: epub_path.clone(),
-                    data,
-                });
+        while let Some((original_url, download_result)) = image_download_futures.next().await {
+            match download_result {
+                Ok(data) => {
+                    // --- SUCCESSFUL DOWNLOAD ---
+                    let extension = html::infer_extension_from_data(&data).unwrap_or("jpg");
+                    let epub_path = format!(
+                        "images/chapter_{}/image_{}.{}",
+                        file_stem, successful_image_index, extension
+                    );
+
+                    // Add the new asset to be bundled with the chapter
+                    images.push(ImageAsset {
+                        epub_path: epub_path.clone(),
+                        data,
+                    });
 
-                // Map the original URL to the new, unique path for this image
-                map.insert(original_url, epub_path);
+                    // Map the original URL to the new, unique path for this image
+                    map.insert(original_url, epub_path);
 
-                successful_image_index += 1;
-            } else {
-                // --- FAILED OR INVALID URL ---
-                // Map the original URL to the global placeholder path.
-                map.insert(original_url, PLACEHOLDER_EPUB_PATH.to_string());
+                    successful_image_index += 1;
+                }
+                Err(ImageFailure::Cancelled) => return Err(AppError::Cancelled),
+                Err(source) if options.strictness.requires_all_images() => {
+                    return Err(AppError::ImageDownloadFailed {
+                        index,
+                        url: original_url,
+                        source,
+                    });
+                }
+                Err(reason) => {
+                    // --- FAILED OR INVALID URL ---
+                    // Map the original URL to the global placeholder path.
+                    map.insert(original_url.clone(), PLACEHOLDER_EPUB_PATH.to_string());
+                    failed_images.push((original_url, reason));
+                }
             }
         }
         map
@@ -390,37 +1344,457 @@ async fn process_chapter(
         HashMap::new()
     };
 
-    let cleaned_html = html::rewrite_and_clean_html(html_in, embed_images, &image_map)?;
+    // Don't hand placeholder-only chapters back once the download was cancelled.
+    ensure_not_cancelled(options)?;
+
+    let embed_images = options.embed_images;
+    let cleaned_html = limits
+        .run_html_task(move || html::rewrite_and_clean_html(&html_in, embed_images, &image_map))
+        .await
+        .map_err(chapter_error)?;
+    let cleaned_html = if options.remove_duplicate_titles {
+        html::remove_duplicate_title(&cleaned_html, &title)
+    } else {
+        cleaned_html
+    };
+    let html_content = match options.chapter_heading.heading(position, &title) {
+        Some(heading) => format!("{}\n{}", chapter_heading(&heading), cleaned_html),
+        None => cleaned_html,
+    };
 
     Ok(ProcessedChapter {
         index,
-        title: title.to_string(),
-        file_name: format!("{}.xhtml", index),
-        html_content: cleaned_html,
+        position,
+        part_id,
+        modify_date,
+        content_hash,
+        title,
+        file_name: format!("{}.xhtml", file_stem),
+        html_content,
         images,
+        image_count,
+        failed_images,
+    })
+}
+
+/// Downloads the story content ZIP and extracts the HTML of every part, keyed by part ID.
+async fn fetch_story_content(
+    wattpad_client: &WattpadClient,
+    story_id: u64,
+    options: &DownloadOptions,
+) -> Result<HashMap<i64, String>, AppError> {
+    let zip_bytes = retry(&options.retry_policy, || {
+        options.throttled(wattpad_client.story.get_story_content_zip(story_id))
+    })
+    .await
+    .map_err(|source| {
+        AppError::from_story_request(story_id, source, |story_id, source| {
+            AppError::DownloadFailed { story_id, source }
+        })
+    })?;
+
+    info!("Successfully downloaded story content ZIP");
+    options.progress.emit(ProgressEvent::ContentDownloaded {
+        bytes: zip_bytes.len(),
+    });
+
+    let mut chapter_html_map: HashMap<i64, String> = HashMap::new();
+    let zip_cursor = Cursor::new(zip_bytes);
+    let invalid_archive = |source| AppError::ContentArchiveInvalid { story_id, source };
+    let mut archive = ZipArchive::new(zip_cursor).map_err(invalid_archive)?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(invalid_archive)?;
+        let file_name = match Path::new(file.name()).file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+
+        if let Ok(part_id) = file_name.parse::<i64>() {
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)
+                .map_err(|e| invalid_archive(e.into()))?;
+            chapter_html_map.insert(part_id, contents);
+        }
+    }
+
+    Ok(chapter_html_map)
+}
+
+/// Limits shared by all chapters of a single story.
+struct StoryLimits {
+    /// Image downloads in flight across all chapters.
+    images: Semaphore,
+    /// Chapters whose HTML is being parsed or rewritten.
+    html_rewrites: Semaphore,
+}
+
+impl StoryLimits {
+    fn new(options: &DownloadOptions) -> Self {
+        Self {
+            images: Semaphore::new(options.concurrent_images),
+            html_rewrites: Semaphore::new(options.concurrent_html_rewrites),
+        }
+    }
+
+    /// Runs CPU-bound HTML work, keeping it off the async executor in non-WASM builds.
+    async fn run_html_task<T, F>(&self, task: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let _permit = self
+            .html_rewrites
+            .acquire()
+            .await
+            .expect("semaphore closed");
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let output = tokio::task::spawn_blocking(task)
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+
+        // No threads to offload to on wasm32
+        #[cfg(target_arch = "wasm32")]
+        let output = task();
+
+        output
+    }
+}
+
+/// Serializes the book into memory, completing an in-memory download.
+pub(crate) fn write_epub_to_memory(
+    mut prepared: PreparedStory,
+    story_id: u64,
+    options: &DownloadOptions,
+) -> Result<StoryDownload<Vec<u8>>, AppError> {
+    let mut cursor = Cursor::new(Vec::new());
+    write_epub(&mut prepared.book, story_id, &mut cursor)?;
+    ensure_not_cancelled(options)?;
+    let epub_bytes = cursor.into_inner();
+
+    options.progress.emit(ProgressEvent::EpubSerialized {
+        bytes: Some(epub_bytes.len() as u64),
+    });
+
+    info!(
+        bytes = epub_bytes.len(),
+        "Successfully generated EPUB in memory"
+    );
+    Ok(StoryDownload {
+        sanitized_title: prepared.sanitized_title,
+        epub_response: epub_bytes,
+        metadata: prepared.metadata,
+        report: prepared.report,
     })
 }
 
-async fn download_image(client: &Client, url: &str) -> Result<Option<Vec<u8>>> {
+/// Serializes the EPUB into `writer`, returning the number of bytes written.
+///
+/// iepub's navigation document has no landmarks, so the book is written into a spool
+/// first and then copied into `writer` with the navigation replaced.
+pub(crate) fn write_epub<W: Write + Seek>(
+    book: &mut EpubBook,
+    story_id: u64,
+    writer: &mut W,
+) -> Result<u64, AppError> {
+    let start = writer.stream_position()?;
+    let mut spool = epub_spool()?;
+    // Chapters carry their own heading, see `chapter_heading`.
+    EpubWriter::new(&mut spool)
+        .with_append_title(false)
+        .write(book)
+        .map_err(|source| AppError::EpubGenerationFailed { story_id, source })?;
+    copy_with_navigation(spool, &navigation_body(book), writer).map_err(|e| {
+        AppError::EpubGenerationFailed {
+            story_id,
+            source: IError::Io(e.into()),
+        }
+    })?;
+    Ok(writer.stream_position()? - start)
+}
+
+/// An anonymous temporary file, keeping the EPUB out of memory while it is written.
+#[cfg(not(target_arch = "wasm32"))]
+fn epub_spool() -> std::io::Result<std::fs::File> {
+    tempfile::tempfile()
+}
+
+/// A buffer in memory, as wasm32 has no temporary files.
+#[cfg(target_arch = "wasm32")]
+fn epub_spool() -> std::io::Result<Cursor<Vec<u8>>> {
+    Ok(Cursor::new(Vec::new()))
+}
+
+/// Returns `AppError::Cancelled` once the download's cancellation token was triggered.
+pub(crate) fn ensure_not_cancelled(options: &DownloadOptions) -> Result<(), AppError> {
+    if options.cancellation.is_cancelled() {
+        return Err(AppError::Cancelled);
+    }
+    Ok(())
+}
+
+/// Writes the EPUB next to `final_path` and only moves it into place once complete,
+/// so failed or cancelled downloads never leave a partial file at `final_path`.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn write_epub_file(
+    mut book: EpubBook,
+    story_id: u64,
+    final_path: &Path,
+    options: &DownloadOptions,
+) -> Result<(), AppError> {
+    let file_name = final_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let partial_path = final_path.with_file_name(format!(".{}.part", file_name));
+
+    let result = std::fs::File::create(&partial_path)
+        .map_err(AppError::from)
+        .and_then(|mut file| write_epub(&mut book, story_id, &mut file))
+        .and_then(|_| {
+            ensure_not_cancelled(options)?;
+            std::fs::rename(&partial_path, final_path)?;
+            Ok(())
+        });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    result
+}
+
+async fn download_image(
+    client: &Client,
+    url: &str,
+    options: &DownloadOptions,
+) -> Result<Vec<u8>, ImageFailure> {
     if reqwest::Url::parse(url).is_err() {
         warn!(
             url,
             "Invalid image URL found. It will be replaced by a placeholder."
         );
-        return Ok(None); // Signal failure for invalid URLs.
+        return Err(ImageFailure::InvalidUrl); // Signal failure for invalid URLs.
     }
 
-    let response = client.get(url).send().await;
+    let shared_images = options.shared_images.as_ref();
+    if let Some(data) = shared_images.and_then(|shared| shared.get(url)) {
+        return Ok(data);
+    }
 
-    match response {
-        Ok(resp) if resp.status().is_success() => Ok(Some(resp.bytes().await?.to_vec())),
-        Ok(resp) => {
-            warn!(status = %resp.status(), url, "Failed to download image (non-success status). Replacing with placeholder.");
-            Ok(None)
+    #[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+    let data = match &options.cache {
+        Some(cache) => download_image_cached(client, url, options, cache).await?,
+        None => download_image_uncached(client, url, options).await?,
+    };
+    #[cfg(target_arch = "wasm32")]
+    let data = download_image_uncached(client, url, options).await?;
+
+    if let Some(shared) = shared_images {
+        shared.insert(url, &data);
+    }
+    Ok(data)
+}
+
+async fn download_image_uncached(
+    client: &Client,
+    url: &str,
+    options: &DownloadOptions,
+) -> Result<Vec<u8>, ImageFailure> {
+    match fetch_image(client, url, options, None).await? {
+        ImageResponse::Downloaded(data, _) => Ok(data),
+        ImageResponse::NotModified => Err(ImageFailure::Status(StatusCode::NOT_MODIFIED)),
+    }
+}
+
+/// Serves an image from the cache, revalidating or downloading it when needed.
+#[cfg(not(target_arch = "wasm32"))]
+async fn download_image_cached(
+    client: &Client,
+    url: &str,
+    options: &DownloadOptions,
+    cache: &DownloadCache,
+) -> Result<Vec<u8>, ImageFailure> {
+    let mut cached = cache.load_image(url);
+    if let Some(fresh) = cached.take_if(|cached| cached.fresh) {
+        return Ok(fresh.data);
+    }
+
+    let validators = cached.as_ref().map(|cached| &cached.validators);
+    match (fetch_image(client, url, options, validators).await?, cached) {
+        (ImageResponse::Downloaded(data, validators), _) => {
+            cache.store_image(url, &data, validators);
+            Ok(data)
         }
-        Err(e) => {
-            warn!(error = %e, url, "Failed to download image (request error). Replacing with placeholder.");
-            Ok(None)
+        (ImageResponse::NotModified, Some(cached)) => {
+            cache.store_image(url, &cached.data, cached.validators);
+            Ok(cached.data)
+        }
+        (ImageResponse::NotModified, None) => Err(ImageFailure::Status(StatusCode::NOT_MODIFIED)),
+    }
+}
+
+/// Requests an image with retries, conditionally when `validators` are given.
+async fn fetch_image(
+    client: &Client,
+    url: &str,
+    options: &DownloadOptions,
+    validators: Option<&ImageValidators>,
+) -> Result<ImageResponse, ImageFailure> {
+    let result = retry(&options.retry_policy, || {
+        options.throttled(download_image_once(client, url, validators))
+    })
+    .await;
+
+    result.map_err(|ImageAttemptError { failure, .. }| {
+        match &failure {
+            ImageFailure::Status(status) => {
+                warn!(%status, url, "Failed to download image (non-success status). Replacing with placeholder.")
+            }
+            e => {
+                warn!(error = %e, url, "Failed to download image (request error). Replacing with placeholder.")
+            }
+        }
+        failure
+    })
+}
+
+/// A single failed image request, carrying the server's `Retry-After` hint.
+#[derive(Debug)]
+struct ImageAttemptError {
+    failure: ImageFailure,
+    retry_after: Option<Duration>,
+}
+
+impl fmt::Display for ImageAttemptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.failure)
+    }
+}
+
+impl Retryable for ImageAttemptError {
+    fn is_retryable(&self) -> bool {
+        match &self.failure {
+            ImageFailure::Status(status) => retry::is_retryable_status(*status),
+            ImageFailure::Request(e) => retry::is_retryable_request_error(e),
+            ImageFailure::InvalidUrl | ImageFailure::Cancelled => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+async fn download_image_once(
+    client: &Client,
+    url: &str,
+    validators: Option<&ImageValidators>,
+) -> Result<ImageResponse, ImageAttemptError> {
+    let failed = |failure| ImageAttemptError {
+        failure,
+        retry_after: None,
+    };
+
+    let mut request = client.get(url);
+    if let Some(validators) = validators {
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let resp = request
+        .send()
+        .await
+        .map_err(|e| failed(ImageFailure::Request(e)))?;
+
+    if validators.is_some() && resp.status() == StatusCode::NOT_MODIFIED {
+        return Ok(ImageResponse::NotModified);
+    }
+    if !resp.status().is_success() {
+        return Err(ImageAttemptError {
+            failure: ImageFailure::Status(resp.status()),
+            retry_after: retry::parse_retry_after(resp.headers()),
+        });
+    }
+
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = ImageValidators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+
+    match resp.bytes().await {
+        Ok(bytes) => Ok(ImageResponse::Downloaded(bytes.to_vec(), validators)),
+        Err(e) => Err(failed(ImageFailure::Request(e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a one-chapter book of story 1 and returns its package document.
+    fn package_document(volume: Option<usize>) -> String {
+        let story: StoryResponse = serde_json::from_str(r#"{"title": "Title"}"#).unwrap();
+        let epub_builder = new_epub_builder(&story, "Title").add_chapter(chapter_html(
+            "Chapter",
+            "1.xhtml",
+            "en",
+            String::new(),
+        ));
+        let mut book = finish_book(epub_builder, 1, &[]).unwrap();
+        if let Some(position) = volume {
+            add_series_meta(&mut book, "Title", position);
+        }
+
+        let mut epub = Cursor::new(Vec::new());
+        write_epub(&mut book, 1, &mut epub).unwrap();
+        let mut archive = ZipArchive::new(epub).unwrap();
+        let mut opf = String::new();
+        archive
+            .by_name("OEBPS/content.opf")
+            .unwrap()
+            .read_to_string(&mut opf)
+            .unwrap();
+        opf
+    }
+
+    #[test]
+    fn writes_an_epub3_package_with_the_story_source() {
+        let opf = package_document(None);
+        assert!(opf.contains(r#"version="3.0""#));
+        let source = r#"<meta property="dcterms:source">https://www.wattpad.com/story/1</meta>"#;
+        assert!(opf.contains(source));
+        assert!(!opf.contains("belongs-to-collection"));
+    }
+
+    #[test]
+    fn writes_the_series_of_a_volume() {
+        let opf = package_document(Some(2));
+        assert!(opf.contains(r#"version="3.0""#));
+        // iepub writes the attributes of a meta in no particular order.
+        let metas: Vec<&str> = opf.split("<meta ").skip(1).collect();
+        for (attributes, text) in [
+            (&[r#"property="belongs-to-collection""#, r#"id="series""#][..], ">Title</meta>"),
+            (&[r##"refines="#series""##, r#"property="collection-type""#], ">series</meta>"),
+            (&[r##"refines="#series""##, r#"property="group-position""#], ">2</meta>"),
+        ] {
+            assert!(
+                metas.iter().any(|meta| {
+                    meta.contains(text) && attributes.iter().all(|attr| meta.contains(attr))
+                }),
+                "{:?}",
+                attributes
+            );
         }
     }
 }
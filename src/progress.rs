@@ -0,0 +1,155 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// A typed progress notification emitted while a story is being downloaded.
+///
+/// Chapter `index` values are 1-based and `total` always equals the number of
+/// selected parts (see [`crate::PartSelection`]), so every chapter ends with exactly one of
+/// `ChapterFinished`, `ChapterFailed`, `ChapterMissing` or, when updating, `ChapterReused`.
+///
+/// Batch downloads run several stories at once, so they wrap every event in
+/// [`ProgressEvent::Story`] to tell the stories apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// The story metadata was fetched.
+    MetadataFetched {
+        /// The story title.
+        title: String,
+        /// The number of selected parts (chapters), the `total` of the chapter events.
+        /// [`crate::fetch_story_plan`] ignores the selection and counts every part.
+        total_chapters: usize,
+    },
+    /// The story content was downloaded.
+    ///
+    /// When every part is needed, the content arrives as one ZIP archive; otherwise the
+    /// selected parts are fetched one by one.
+    ContentDownloaded {
+        /// Size of the ZIP archive in bytes, or the total size of the HTML of the
+        /// parts fetched one by one.
+        bytes: usize,
+    },
+    /// Every part was found in the [`crate::DownloadCache`], so the content ZIP was not downloaded.
+    ///
+    /// Emitted instead of `ContentDownloaded`.
+    ContentLoadedFromCache {
+        /// The number of parts loaded.
+        parts: usize,
+    },
+    /// A part listed in the metadata had no content, or no ID, and is skipped.
+    ChapterMissing {
+        /// The Wattpad part ID, `0` when the metadata lacks it.
+        part_id: u64,
+        /// The total number of chapters.
+        total: usize,
+    },
+    /// Processing of a chapter started.
+    ChapterStarted {
+        /// The chapter index.
+        index: usize,
+        /// The total number of chapters.
+        total: usize,
+        /// The chapter title.
+        title: String,
+    },
+    /// The images of a chapter were collected and are about to be downloaded.
+    ChapterImagesFound {
+        /// The chapter index.
+        index: usize,
+        /// The number of images referenced by the chapter.
+        count: usize,
+    },
+    /// An image was downloaded and will be embedded.
+    ImageDownloaded {
+        /// The chapter index.
+        index: usize,
+        /// The original image URL.
+        url: String,
+        /// Size of the image in bytes.
+        bytes: usize,
+    },
+    /// An image could not be downloaded and was replaced by the placeholder.
+    ImagePlaceholdered {
+        /// The chapter index.
+        index: usize,
+        /// The original image URL.
+        url: String,
+    },
+    /// A chapter was processed successfully.
+    ChapterFinished {
+        /// The chapter index.
+        index: usize,
+        /// The total number of chapters.
+        total: usize,
+    },
+    /// An unchanged chapter was carried over from the existing EPUB during an update.
+    ChapterReused {
+        /// The chapter index.
+        index: usize,
+        /// The total number of chapters.
+        total: usize,
+    },
+    /// A chapter failed to process and is left out of the EPUB.
+    ChapterFailed {
+        /// The chapter index.
+        index: usize,
+        /// The total number of chapters.
+        total: usize,
+    },
+    /// The cover image was fetched.
+    CoverFetched {
+        /// Size of the cover in bytes.
+        bytes: usize,
+    },
+    /// The EPUB was serialized to its output.
+    EpubSerialized {
+        /// Size of the EPUB in bytes, when known.
+        bytes: Option<u64>,
+    },
+    /// An event of one story of a batch download, see [`crate::download_stories_to_memory`].
+    Story {
+        /// The Wattpad story ID.
+        story_id: u64,
+        /// The event, as a single-story download would have emitted it.
+        event: Box<ProgressEvent>,
+    },
+}
+
+/// A callback receiving [`ProgressEvent`]s, shared between the download tasks.
+#[derive(Clone, Default)]
+pub(crate) struct ProgressReporter(Option<Arc<dyn Fn(ProgressEvent) + Send + Sync>>);
+
+impl ProgressReporter {
+    pub(crate) fn new(callback: Arc<dyn Fn(ProgressEvent) + Send + Sync>) -> Self {
+        Self(Some(callback))
+    }
+
+    /// A reporter wrapping every event in [`ProgressEvent::Story`] before passing it on.
+    pub(crate) fn for_story(&self, story_id: u64) -> Self {
+        match &self.0 {
+            Some(callback) => {
+                let callback = Arc::clone(callback);
+                Self::new(Arc::new(move |event| {
+                    callback(ProgressEvent::Story {
+                        story_id,
+                        event: Box::new(event),
+                    })
+                }))
+            }
+            None => Self::default(),
+        }
+    }
+
+    pub(crate) fn emit(&self, event: ProgressEvent) {
+        if let Some(callback) = &self.0 {
+            callback(event);
+        }
+    }
+}
+
+impl fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ProgressReporter")
+            .field(&self.0.as_ref().map(|_| "Fn(ProgressEvent)"))
+            .finish()
+    }
+}
@@ -0,0 +1,130 @@
+use crate::retry;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use web_time::Instant;
+
+/// Caps the request rate and the number of requests in flight.
+///
+/// Cloning a `RateLimiter` is cheap and the clones share their limits, so one
+/// limiter can be passed to several simultaneous downloads through
+/// [`crate::DownloadOptionsBuilder::rate_limiter`]. Every HTTP request of a
+/// download (metadata, content ZIP, images, cover and retries) goes through it.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    semaphore: Option<Semaphore>,
+    bucket: Option<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a new builder. Without further settings the limiter does not limit anything.
+    pub fn builder() -> RateLimiterBuilder {
+        RateLimiterBuilder::default()
+    }
+
+    /// Waits until the limits allow another request, then runs it.
+    pub(crate) async fn run<F: Future>(&self, request: F) -> F::Output {
+        // Wait for a free slot first so tokens are only spent on requests about to be sent.
+        let _permit = match &self.inner.semaphore {
+            // The semaphore is never closed.
+            Some(semaphore) => Some(semaphore.acquire().await.expect("semaphore closed")),
+            None => None,
+        };
+        if let Some(bucket) = &self.inner.bucket {
+            take_token(bucket).await;
+        }
+        request.await
+    }
+}
+
+/// A builder for creating a [`RateLimiter`].
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiterBuilder {
+    requests_per_second: Option<f64>,
+    burst: Option<u32>,
+    max_concurrent_requests: Option<usize>,
+}
+
+impl RateLimiterBuilder {
+    /// Set the sustained number of requests started per second. Unlimited by default.
+    /// Non-positive values are ignored.
+    pub fn requests_per_second(mut self, requests_per_second: f64) -> Self {
+        self.requests_per_second = (requests_per_second > 0.0).then_some(requests_per_second);
+        self
+    }
+
+    /// Set how many requests may be started at once before the rate applies.
+    /// Defaults to one second's worth of requests. A value of `0` is treated as `1`.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = Some(burst.max(1));
+        self
+    }
+
+    /// Set the maximum number of requests in flight at once. Unlimited by default.
+    /// A value of `0` is treated as `1`.
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = Some(max_concurrent_requests.max(1));
+        self
+    }
+
+    /// Builds the `RateLimiter`.
+    pub fn build(self) -> RateLimiter {
+        let bucket = self.requests_per_second.map(|rate| {
+            let burst = self.burst.map_or(rate.ceil().max(1.0), f64::from);
+            Mutex::new(TokenBucket {
+                rate,
+                burst,
+                tokens: burst,
+                last_refill: Instant::now(),
+            })
+        });
+
+        RateLimiter {
+            inner: Arc::new(Inner {
+                semaphore: self.max_concurrent_requests.map(Semaphore::new),
+                bucket,
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Takes a token if one is available, otherwise returns how long until one is.
+    fn try_take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+async fn take_token(bucket: &Mutex<TokenBucket>) {
+    loop {
+        let result = bucket.lock().expect("token bucket poisoned").try_take();
+        match result {
+            Ok(()) => return,
+            Err(wait) => retry::sleep(wait).await,
+        }
+    }
+}
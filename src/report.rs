@@ -0,0 +1,110 @@
+use crate::error::AppError;
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Describes what ended up in a generated EPUB and what was left out.
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+    /// The number of parts selected for download, all parts listed in the story metadata by default.
+    pub total_chapters: usize,
+    /// The number of chapters written to the EPUB.
+    pub included_chapters: usize,
+    /// The number of included chapters carried over unchanged from the existing EPUB
+    /// by an update. Their images are not counted in the image totals below.
+    pub reused_chapters: usize,
+    /// Parts listed in the metadata that have no content to include, see [`MissingReason`].
+    pub missing_chapters: Vec<MissingChapter>,
+    /// Chapters that failed to process and were left out of the EPUB.
+    pub failed_chapters: Vec<FailedChapter>,
+    /// The number of images referenced by the included chapters.
+    pub total_images: usize,
+    /// The number of images embedded successfully.
+    pub embedded_images: usize,
+    /// Images that were replaced by the placeholder image.
+    pub placeholder_images: Vec<PlaceholderImage>,
+    /// Whether the cover was embedded.
+    pub cover: CoverStatus,
+}
+
+impl DownloadReport {
+    /// Returns `true` when every chapter, image and the cover made it into the EPUB.
+    pub fn is_complete(&self) -> bool {
+        self.missing_chapters.is_empty()
+            && self.failed_chapters.is_empty()
+            && self.placeholder_images.is_empty()
+            && !matches!(self.cover, CoverStatus::Failed(_))
+    }
+}
+
+/// A part listed in the story metadata that has no content to include.
+#[derive(Debug, Clone)]
+pub struct MissingChapter {
+    /// The Wattpad part ID, `0` when the metadata lacks it.
+    pub part_id: u64,
+    /// The part title.
+    pub title: String,
+    /// Why the part has no content.
+    pub reason: MissingReason,
+}
+
+/// Why a part has no content.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingReason {
+    #[error("absent from the story content")]
+    NotInContent,
+
+    #[error("no part id")]
+    NoPartId,
+}
+
+/// A chapter that failed to process.
+#[derive(Debug)]
+pub struct FailedChapter {
+    /// The 1-based chapter index.
+    pub index: usize,
+    /// The Wattpad part ID.
+    pub part_id: u64,
+    /// The part title.
+    pub title: String,
+    /// Why processing failed.
+    pub error: AppError,
+}
+
+/// An image replaced by the placeholder image.
+#[derive(Debug)]
+pub struct PlaceholderImage {
+    /// The 1-based index of the chapter referencing the image.
+    pub chapter_index: usize,
+    /// The original image URL.
+    pub url: String,
+    /// Why the image could not be embedded.
+    pub reason: ImageFailure,
+}
+
+/// Why an image could not be downloaded.
+#[derive(Error, Debug)]
+pub enum ImageFailure {
+    #[error("Invalid image URL")]
+    InvalidUrl,
+
+    #[error("Server responded with {0}")]
+    Status(StatusCode),
+
+    #[error("Request failed")]
+    Request(#[source] reqwest::Error),
+
+    #[error("The download was cancelled")]
+    Cancelled,
+}
+
+/// What happened to the story cover.
+#[derive(Debug, Default)]
+pub enum CoverStatus {
+    /// The story has no cover.
+    #[default]
+    Missing,
+    /// The cover was embedded.
+    Embedded,
+    /// The cover could not be downloaded.
+    Failed(ImageFailure),
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use wp_mini::types::PartStubResponse;
+
+type PartFilter = Arc<dyn Fn(&PartStubResponse) -> bool + Send + Sync>;
+
+/// Chooses which parts of a story end up in the EPUB.
+///
+/// Parts keep their story order regardless of how they were selected. When not
+/// every part is selected, the chapters are fetched one by one instead of through
+/// the content ZIP and the EPUB title names the selection, e.g. `Title (Parts 40–60)`.
+#[derive(Clone, Default)]
+pub struct PartSelection {
+    kind: SelectionKind,
+}
+
+#[derive(Clone, Default)]
+enum SelectionKind {
+    #[default]
+    All,
+    /// 1-based, inclusive.
+    Range {
+        start: usize,
+        end: Option<usize>,
+    },
+    Ids(Vec<u64>),
+    Filter(PartFilter),
+}
+
+impl PartSelection {
+    /// Selects every part. This is the default.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Selects parts by their 1-based position in the story, e.g. `40..=60` or `..10`.
+    pub fn range(range: impl RangeBounds<usize>) -> Self {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start.max(1),
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 1,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => Some(end),
+            Bound::Excluded(&end) => Some(end.saturating_sub(1)),
+            Bound::Unbounded => None,
+        };
+        Self {
+            kind: SelectionKind::Range { start, end },
+        }
+    }
+
+    /// Selects the parts with the given Wattpad part IDs.
+    pub fn ids(part_ids: impl IntoIterator<Item = u64>) -> Self {
+        Self {
+            kind: SelectionKind::Ids(part_ids.into_iter().collect()),
+        }
+    }
+
+    /// Selects the parts for which `filter` returns `true`.
+    ///
+    /// The filter sees the part fields the download requests (ID, title and modify date).
+    /// Request more with [`crate::DownloadOptionsBuilder::extra_fields`] and
+    /// [`crate::StoryField::Parts`].
+    pub fn filter<F>(filter: F) -> Self
+    where
+        F: Fn(&PartStubResponse) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            kind: SelectionKind::Filter(Arc::new(filter)),
+        }
+    }
+
+    /// Whether every part is selected, without looking at the story.
+    pub fn is_all(&self) -> bool {
+        matches!(self.kind, SelectionKind::All)
+    }
+
+    fn contains(&self, position: usize, part: &PartStubResponse) -> bool {
+        match &self.kind {
+            SelectionKind::All => true,
+            SelectionKind::Range { start, end } => {
+                position >= *start && end.is_none_or(|end| position <= end)
+            }
+            SelectionKind::Ids(ids) => part.id.is_some_and(|id| ids.contains(&id)),
+            SelectionKind::Filter(filter) => filter(part),
+        }
+    }
+
+    /// Keeps the selected parts, paired with their 1-based position in the story.
+    pub(crate) fn apply(&self, parts: Vec<PartStubResponse>) -> Vec<(usize, PartStubResponse)> {
+        parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, part)| (i + 1, part))
+            .filter(|(position, part)| self.contains(*position, part))
+            .collect()
+    }
+}
+
+impl fmt::Debug for PartSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            SelectionKind::All => f.write_str("PartSelection::All"),
+            SelectionKind::Range { start, end } => f
+                .debug_struct("PartSelection::Range")
+                .field("start", start)
+                .field("end", end)
+                .finish(),
+            SelectionKind::Ids(ids) => f.debug_tuple("PartSelection::Ids").field(ids).finish(),
+            SelectionKind::Filter(_) => f.write_str("PartSelection::Filter(..)"),
+        }
+    }
+}
+
+/// The EPUB title for a story of which only the parts at `positions` were selected.
+pub(crate) fn selection_title(title: &str, positions: &[usize], total: usize) -> String {
+    let (Some(&first), Some(&last)) = (positions.first(), positions.last()) else {
+        return title.to_string();
+    };
+    if positions.len() == total {
+        title.to_string()
+    } else if first == last {
+        format!("{} (Part {})", title, first)
+    } else if last - first + 1 == positions.len() {
+        format!("{} (Parts {}–{})", title, first, last)
+    } else {
+        format!("{} ({} of {} Parts)", title, positions.len(), total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parts with the IDs 101, 102, … at the positions 1, 2, ….
+    fn parts(count: u64) -> Vec<PartStubResponse> {
+        (1..=count)
+            .map(|n| serde_json::from_str(&format!(r#"{{"id": {}}}"#, 100 + n)).unwrap())
+            .collect()
+    }
+
+    fn positions(selection: PartSelection, count: u64) -> Vec<usize> {
+        selection
+            .apply(parts(count))
+            .into_iter()
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    #[test]
+    fn selects_inclusive_and_exclusive_ranges() {
+        assert_eq!(positions(PartSelection::range(2..=4), 5), [2, 3, 4]);
+        assert_eq!(positions(PartSelection::range(2..4), 5), [2, 3]);
+        assert_eq!(positions(PartSelection::range(..=2), 5), [1, 2]);
+        assert_eq!(positions(PartSelection::range(4..), 5), [4, 5]);
+        assert_eq!(positions(PartSelection::range(0..2), 5), [1]);
+        let excluded = (Bound::Excluded(2), Bound::Included(4));
+        assert_eq!(positions(PartSelection::range(excluded), 5), [3, 4]);
+    }
+
+    #[test]
+    fn selects_nothing_from_empty_ranges() {
+        assert!(positions(PartSelection::range(3..3), 5).is_empty());
+        let reversed = (Bound::Included(4), Bound::Included(2));
+        assert!(positions(PartSelection::range(reversed), 5).is_empty());
+        assert!(positions(PartSelection::range(..1), 5).is_empty());
+        assert!(positions(PartSelection::range(6..), 5).is_empty());
+        let past_the_end = (Bound::Excluded(usize::MAX), Bound::Unbounded);
+        assert!(positions(PartSelection::range(past_the_end), 5).is_empty());
+    }
+
+    #[test]
+    fn selects_existing_ids_in_story_order() {
+        assert_eq!(positions(PartSelection::ids([104, 102]), 5), [2, 4]);
+        assert_eq!(positions(PartSelection::ids([104, 999]), 5), [4]);
+        assert!(positions(PartSelection::ids([999]), 5).is_empty());
+    }
+
+    #[test]
+    fn names_the_selection_in_the_title() {
+        assert_eq!(selection_title("Title", &[1, 2, 3], 3), "Title");
+        assert_eq!(selection_title("Title", &[], 3), "Title");
+        assert_eq!(selection_title("Title", &[2], 3), "Title (Part 2)");
+        assert_eq!(selection_title("Title", &[2, 3, 4], 5), "Title (Parts 2–4)");
+        assert_eq!(selection_title("Title", &[1, 3], 5), "Title (2 of 5 Parts)");
+    }
+}
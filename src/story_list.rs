@@ -0,0 +1,313 @@
+use crate::batch::{BatchSummary, download_stories_to_folder};
+use crate::cache::write_atomically;
+use crate::error::AppError;
+use crate::options::DownloadOptions;
+use crate::processor::{ensure_not_cancelled, generator, now_timestamp};
+use crate::retry::retry;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::{info, instrument};
+use wp_mini::field::StoryField;
+use wp_mini::types::UserStoriesResponse;
+use wp_mini::{WattpadClient, WattpadError};
+
+/// The number of stories requested per page. The API caps pages at 100.
+const PAGE_SIZE: usize = 100;
+
+/// The name of the manifest written next to the EPUBs.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// A list of stories on Wattpad that can be downloaded as a whole.
+///
+/// Excluded for wasm32
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoryList {
+    /// A reading list, by its ID as seen in `https://www.wattpad.com/list/{id}`.
+    /// Private reading lists need the `WattpadClient` to be logged in, see [`crate::login`].
+    ReadingList(u64),
+    /// Every story published by a user, by username.
+    User(String),
+}
+
+impl fmt::Display for StoryList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoryList::ReadingList(list_id) => write!(f, "reading list {}", list_id),
+            StoryList::User(username) => write!(f, "stories of user {}", username),
+        }
+    }
+}
+
+/// The result of [`download_story_list_to_folder`].
+///
+/// Excluded for wasm32
+#[derive(Debug, Clone)]
+pub struct StoryListDownload {
+    /// What was downloaded, as written to [`StoryListDownload::manifest_path`].
+    pub manifest: StoryListManifest,
+    /// The path of the written manifest.
+    pub manifest_path: PathBuf,
+    /// Aggregated outcome of the stories.
+    pub summary: BatchSummary,
+}
+
+/// The manifest written next to the EPUBs of a story list, as `manifest.json`.
+///
+/// Excluded for wasm32
+#[derive(Debug, Clone, Serialize)]
+pub struct StoryListManifest {
+    /// The list the stories came from.
+    pub list: StoryList,
+    /// When the download finished, e.g. `2024-01-31T12:00:00Z`.
+    pub downloaded_at: String,
+    /// The crate name and version that wrote the manifest.
+    pub generator: String,
+    /// Every story of the list, in list order.
+    pub stories: Vec<ManifestEntry>,
+}
+
+/// A story in a [`StoryListManifest`].
+///
+/// Excluded for wasm32
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestEntry {
+    /// The Wattpad story ID.
+    pub story_id: u64,
+    /// The story title. `None` when the download failed.
+    pub title: Option<String>,
+    /// The EPUB file names, relative to the output folder. Several when the story was
+    /// split into volumes, none when the download failed.
+    pub files: Vec<String>,
+    /// How the download went.
+    pub status: ManifestStatus,
+    /// The number of chapters in the EPUB.
+    pub chapters: usize,
+    /// Why the download failed.
+    pub error: Option<String>,
+}
+
+/// The outcome of a story in a [`StoryListManifest`].
+///
+/// Excluded for wasm32
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestStatus {
+    /// The EPUB is complete.
+    Complete,
+    /// The EPUB was written, but with missing chapters, images or cover.
+    Incomplete,
+    /// No EPUB was written.
+    Failed,
+}
+
+/// Lists the IDs of the stories in a reading list or published by a user.
+///
+/// Pages through the whole list, following the API's `nextUrl`. Every page request is sent
+/// through `wattpad_client`, with its session, and goes through the rate limiter, retry
+/// policy and cancellation token of `options`. Duplicates are kept, in list order.
+///
+/// Excluded for wasm32
+///
+/// # Arguments
+/// * `list` - The reading list or user.
+/// * `options` - The download settings, see [`DownloadOptions`].
+///
+/// # Returns
+/// A `Result` containing the story IDs, in list order.
+#[instrument(skip(wattpad_client, options))]
+pub async fn fetch_story_list_ids(
+    wattpad_client: &WattpadClient,
+    list: &StoryList,
+    options: &DownloadOptions,
+) -> Result<Vec<u64>, AppError> {
+    let mut story_ids = Vec::new();
+    let mut offset = 0;
+    loop {
+        let page = options
+            .cancellation
+            .run_until_cancelled(retry(&options.retry_policy, || {
+                options.throttled(fetch_page(wattpad_client, list, offset))
+            }))
+            .await
+            .ok_or(AppError::Cancelled)?
+            .map_err(|source| list_error(list, source))?;
+
+        let page_len = page.stories.len();
+        story_ids.extend(
+            page.stories
+                .iter()
+                .filter_map(|story| story.id.as_deref()?.parse::<u64>().ok()),
+        );
+        // An empty page ends the list too, so a stray `nextUrl` cannot loop forever.
+        if page.next_url.is_none() || page_len == 0 {
+            break;
+        }
+        offset += page_len;
+    }
+
+    info!(count = story_ids.len(), "Fetched story list");
+    Ok(story_ids)
+}
+
+/// Downloads every story of a reading list or user into `output_path`, and writes a
+/// [`MANIFEST_FILE_NAME`] file describing the outcome of each story.
+///
+/// Each story gets its own EPUB, named like [`crate::download_story_to_folder`] names it.
+/// The stories are downloaded as a batch, see [`crate::download_stories_to_memory`].
+/// A story failing does not stop the others; only listing the stories or writing the
+/// manifest fails the whole call.
+///
+/// Excluded for wasm32
+///
+/// # Arguments
+/// * `list` - The reading list or user.
+/// * `options` - The download settings, applied to every story.
+/// * `output_path` - The directory where the `.epub` files and the manifest will be saved.
+///
+/// # Returns
+/// A `Result` containing the [`StoryListDownload`].
+#[instrument(skip(wattpad_client, reqwest_client, options, output_path))]
+pub async fn download_story_list_to_folder(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    list: &StoryList,
+    options: &DownloadOptions,
+    output_path: &Path,
+) -> Result<StoryListDownload, AppError> {
+    let story_ids = fetch_story_list_ids(wattpad_client, list, options).await?;
+
+    let mut batch = download_stories_to_folder(
+        wattpad_client,
+        reqwest_client,
+        story_ids.iter().copied(),
+        options,
+        output_path,
+    );
+    let mut entries = Vec::new();
+    while let Some(item) = batch.next().await {
+        let entry = match item.result {
+            Ok(download) => ManifestEntry {
+                story_id: item.story_id,
+                title: download.metadata.title,
+                files: download
+                    .epub_response
+                    .iter()
+                    .filter_map(|path| path.file_name())
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .collect(),
+                status: if download.report.is_complete() {
+                    ManifestStatus::Complete
+                } else {
+                    ManifestStatus::Incomplete
+                },
+                chapters: download.report.included_chapters,
+                error: None,
+            },
+            Err(e) => ManifestEntry {
+                story_id: item.story_id,
+                title: None,
+                files: Vec::new(),
+                status: ManifestStatus::Failed,
+                chapters: 0,
+                error: Some(e.to_string()),
+            },
+        };
+        entries.push(entry);
+    }
+    let summary = batch.summary().clone();
+    ensure_not_cancelled(options)?;
+
+    // The batch yields stories as they finish; the manifest follows the list.
+    entries.sort_by_key(|entry| story_ids.iter().position(|id| *id == entry.story_id));
+
+    let manifest = StoryListManifest {
+        list: list.clone(),
+        downloaded_at: now_timestamp(),
+        generator: generator(),
+        stories: entries,
+    };
+
+    let manifest_path = output_path.join(MANIFEST_FILE_NAME);
+    let json = serde_json::to_vec_pretty(&manifest).map_err(std::io::Error::from)?;
+    write_atomically(&manifest_path, &json)?;
+
+    info!(
+        path = %manifest_path.display(),
+        succeeded = summary.succeeded.len(),
+        incomplete = summary.incomplete.len(),
+        failed = summary.failed.len(),
+        "Downloaded story list"
+    );
+    Ok(StoryListDownload {
+        manifest,
+        manifest_path,
+        summary,
+    })
+}
+
+async fn fetch_page(
+    wattpad_client: &WattpadClient,
+    list: &StoryList,
+    offset: usize,
+) -> Result<UserStoriesResponse, WattpadError> {
+    wattpad_client
+        .user
+        .get_user_stories(&stories_owner(list), Some(&[StoryField::Id]))?
+        .limit(PAGE_SIZE)
+        .offset(offset)
+        .execute()
+        .await
+}
+
+/// What to pass to wp-mini's user stories endpoint to page through `list`.
+///
+/// wp-mini has no reading list endpoint, but the list endpoint answers like the user
+/// stories one, and `/api/v3/users/../lists/{id}/stories` normalizes to it. That way the
+/// request is still sent by the `WattpadClient`, with its session.
+fn stories_owner(list: &StoryList) -> String {
+    match list {
+        StoryList::ReadingList(list_id) => format!("../lists/{}", list_id),
+        StoryList::User(username) => username.clone(),
+    }
+}
+
+fn list_error(list: &StoryList, source: WattpadError) -> AppError {
+    match (list, source) {
+        (StoryList::User(username), WattpadError::UserNotFound) => AppError::UserNotFound {
+            username: username.clone(),
+        },
+        (list, source) => AppError::StoryListFetchFailed {
+            list: list.to_string(),
+            source,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Url;
+
+    #[test]
+    fn pages_reading_lists_through_the_user_stories_endpoint() {
+        let stories_url = |list: &StoryList| {
+            let url = format!(
+                "https://www.wattpad.com/api/v3/users/{}/stories",
+                stories_owner(list)
+            );
+            Url::parse(&url).unwrap().path().to_string()
+        };
+        assert_eq!(
+            stories_url(&StoryList::ReadingList(42)),
+            "/api/v3/lists/42/stories"
+        );
+        assert_eq!(
+            stories_url(&StoryList::User("someone".to_string())),
+            "/api/v3/users/someone/stories"
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use crate::error::AppError;
+use crate::options::DownloadOptions;
+use iepub::prelude::{EpubAssets, EpubBook, EpubLink, LinkRel};
+use std::fmt;
+use std::sync::Arc;
+
+/// The stylesheet every chapter links, relative to the chapters.
+const STYLESHEET_FILE_NAME: &str = "styles/book.css";
+
+/// The folder embedded fonts are stored in, relative to the chapters.
+const FONTS_DIR: &str = "fonts";
+
+/// A built-in look for the chapters, see [`crate::DownloadOptionsBuilder::theme`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    /// Justified paragraphs without spacing, indented like a printed book.
+    ClassicBook,
+    /// Unindented paragraphs separated by a blank line, like the Wattpad website.
+    WebSpacing,
+    /// Black on white with spaced paragraphs and underlined links, for e-ink screens.
+    EInkContrast,
+}
+
+impl Theme {
+    fn css(self) -> &'static str {
+        match self {
+            Theme::ClassicBook => {
+                "p { margin: 0; text-indent: 1.5em; text-align: justify; }\n\
+                 h1 + p, p[style*=\"center\"] { text-indent: 0; }\n\
+                 h1 { margin: 1em 0 1.5em; }\n\
+                 img { max-width: 100%; }\n"
+            }
+            Theme::WebSpacing => {
+                "p { margin: 0 0 1em; text-indent: 0; line-height: 1.5; }\n\
+                 h1 { margin: 0.5em 0 1em; }\n\
+                 img { max-width: 100%; }\n"
+            }
+            Theme::EInkContrast => {
+                "body { color: #000; background-color: #fff; }\n\
+                 p { margin: 0 0 0.8em; line-height: 1.4; }\n\
+                 h1 { font-weight: bold; margin: 0.5em 0 1em; }\n\
+                 a { color: #000; text-decoration: underline; }\n\
+                 img { max-width: 100%; }\n"
+            }
+        }
+    }
+}
+
+/// A font embedded into the EPUB and declared with `@font-face`.
+///
+/// Embedding a font does not apply it; use its family in a
+/// [`crate::DownloadOptionsBuilder::stylesheet`], e.g. `body { font-family: "Literata"; }`.
+#[derive(Clone)]
+pub struct EmbeddedFont {
+    family: String,
+    file_name: String,
+    data: Arc<[u8]>,
+    weight: Option<u16>,
+    italic: bool,
+}
+
+impl EmbeddedFont {
+    /// Creates a font of the given family from the contents of a font file.
+    ///
+    /// # Arguments
+    /// * `family` - The family name the stylesheet refers to.
+    /// * `file_name` - The name of the file inside the EPUB, e.g. `Literata-Regular.ttf`.
+    ///   The extension decides the media type written to the EPUB, and only `.ttf` gets a
+    ///   correct one, so convert other formats to TrueType first.
+    /// * `data` - The contents of the font file.
+    ///
+    /// # Returns
+    /// A `Result` containing the font, or [`AppError::InvalidFontFileName`] when the file
+    /// name is not a `.ttf` name or contains a folder.
+    pub fn new(
+        family: impl Into<String>,
+        file_name: impl Into<String>,
+        data: Vec<u8>,
+    ) -> Result<Self, AppError> {
+        let file_name = file_name.into();
+        if !is_valid_font_file_name(&file_name) {
+            return Err(AppError::InvalidFontFileName { file_name });
+        }
+        Ok(EmbeddedFont {
+            family: family.into(),
+            file_name,
+            data: data.into(),
+            weight: None,
+            italic: false,
+        })
+    }
+
+    /// Set the `font-weight` this file provides, e.g. `700` for bold. Defaults to any weight.
+    pub fn weight(mut self, weight: u16) -> Self {
+        self.weight = Some(weight);
+        self
+    }
+
+    /// Set whether this file provides the italic style. Defaults to `false`.
+    pub fn italic(mut self, italic: bool) -> Self {
+        self.italic = italic;
+        self
+    }
+
+    /// The family name the stylesheet refers to.
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    /// The name of the file inside the EPUB.
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    fn font_face(&self) -> String {
+        let mut rule = format!(
+            "@font-face {{ font-family: \"{}\"; src: url(\"../{}/{}\");",
+            css_string(&self.family),
+            FONTS_DIR,
+            self.file_name
+        );
+        if let Some(weight) = self.weight {
+            rule.push_str(&format!(" font-weight: {};", weight));
+        }
+        if self.italic {
+            rule.push_str(" font-style: italic;");
+        }
+        rule.push_str(" }\n");
+        rule
+    }
+}
+
+/// Whether `file_name` names a TrueType file right inside the fonts folder, and can be
+/// put into a CSS string as is.
+fn is_valid_font_file_name(file_name: &str) -> bool {
+    let is_ttf = file_name
+        .rsplit_once('.')
+        .is_some_and(|(stem, extension)| !stem.is_empty() && extension.eq_ignore_ascii_case("ttf"));
+    is_ttf
+        && !file_name
+            .chars()
+            .any(|c| matches!(c, '/' | '\\' | '"') || c.is_control())
+}
+
+/// Escapes `value` for a double-quoted CSS string.
+fn css_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            // Control characters, newlines included, end a string; use their code point.
+            c if c.is_control() => escaped.push_str(&format!("\\{:x} ", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl fmt::Debug for EmbeddedFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmbeddedFont")
+            .field("family", &self.family)
+            .field("file_name", &self.file_name)
+            .field("bytes", &self.data.len())
+            .field("weight", &self.weight)
+            .field("italic", &self.italic)
+            .finish()
+    }
+}
+
+/// Adds the stylesheet chosen by `options`, and the fonts it declares, to the book, and
+/// links it from every chapter. Books without a theme, stylesheet or font are left as is.
+///
+/// The stylesheet holds the font faces first, then the theme, then the user's rules, so
+/// the user's rules win over the theme.
+pub(crate) fn add_stylesheet(book: &mut EpubBook, options: &DownloadOptions) {
+    if options.theme.is_none() && options.stylesheet.is_none() && options.fonts.is_empty() {
+        return;
+    }
+
+    let mut css = String::new();
+    for font in &options.fonts {
+        css.push_str(&font.font_face());
+        book.add_assets(
+            EpubAssets::default()
+                .with_file_name(format!("{}/{}", FONTS_DIR, font.file_name))
+                .with_data(font.data.to_vec()),
+        );
+    }
+    if let Some(theme) = options.theme {
+        css.push_str(theme.css());
+    }
+    if let Some(stylesheet) = &options.stylesheet {
+        css.push_str(stylesheet);
+    }
+    book.add_assets(
+        EpubAssets::default()
+            .with_file_name(STYLESHEET_FILE_NAME)
+            .with_data(css.into_bytes()),
+    );
+
+    for chapter in book.chapters_mut() {
+        chapter.add_link(EpubLink {
+            rel: LinkRel::CSS,
+            file_type: "text/css".to_string(),
+            href: STYLESHEET_FILE_NAME.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_plain_ttf_file_names() {
+        assert!(is_valid_font_file_name("Literata-Regular.ttf"));
+        assert!(is_valid_font_file_name("Literata.TTF"));
+        for file_name in [
+            "Literata.woff",
+            "Literata.otf",
+            ".ttf",
+            "../Literata.ttf",
+            "fonts/Literata.ttf",
+            "fonts\\Literata.ttf",
+            "Lite\"rata.ttf",
+            "ü",
+        ] {
+            assert!(!is_valid_font_file_name(file_name), "{}", file_name);
+        }
+    }
+
+    #[test]
+    fn escapes_the_family_in_the_font_face() {
+        let font = EmbeddedFont::new("Evil\"; }\\\n", "Evil.ttf", Vec::new()).unwrap();
+        assert_eq!(
+            font.font_face(),
+            "@font-face { font-family: \"Evil\\\"; }\\\\\\a \"; src: url(\"../fonts/Evil.ttf\"); }\n"
+        );
+    }
+}
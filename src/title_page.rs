@@ -0,0 +1,112 @@
+use crate::processor::{story_author, story_url};
+use quick_xml::escape::escape;
+use wp_mini::types::StoryResponse;
+
+/// The file name of the title page of a single-story EPUB.
+pub(crate) const TITLE_PAGE_FILE_NAME: &str = "title_page.xhtml";
+
+/// The body of the front-matter page describing a story.
+///
+/// Shows whatever the story metadata holds: tags, completion status and the mature flag
+/// are requested along with the title page, counts and dates only when the caller asked
+/// for them through [`crate::DownloadOptionsBuilder::extra_fields`].
+pub(crate) fn title_page_body(
+    story_id: u64,
+    story: &StoryResponse,
+    title: &str,
+    cover_path: Option<&str>,
+    downloaded_at: &str,
+) -> String {
+    let mut body = format!(
+        r#"<section epub:type="titlepage" data-story-id="{}" data-source="{}">"#,
+        story_id,
+        story_url(story_id)
+    );
+    body.push_str(&format!(
+        r#"<h1 style="text-align: center">{}</h1><p style="text-align: center">by {}</p>"#,
+        escape(title),
+        escape(story_author(story))
+    ));
+    if let Some(cover_path) = cover_path {
+        body.push_str(&format!(
+            r#"<div style="text-align: center"><img src="{}" alt="{}" style="max-width: 50%"/></div>"#,
+            escape(cover_path),
+            escape(title)
+        ));
+    }
+    for paragraph in story
+        .description
+        .as_deref()
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        body.push_str(&format!("<p>{}</p>", escape(paragraph)));
+    }
+
+    body.push_str("<dl>");
+    for (label, value) in story_details(story_id, story, downloaded_at) {
+        body.push_str(&format!("<dt>{}</dt><dd>{}</dd>", label, value));
+    }
+    body.push_str("</dl></section>");
+    body
+}
+
+/// The labelled details of a story, with values already escaped.
+fn story_details(
+    story_id: u64,
+    story: &StoryResponse,
+    downloaded_at: &str,
+) -> Vec<(&'static str, String)> {
+    let mut details = Vec::new();
+    if let Some(tags) = story.tags.as_ref().filter(|tags| !tags.is_empty()) {
+        details.push(("Tags", escape(tags.join(", ")).into_owned()));
+    }
+    if let Some(completed) = story.completed {
+        let status = if completed { "Completed" } else { "Ongoing" };
+        details.push(("Status", status.to_string()));
+    }
+    if let Some(mature) = story.mature {
+        details.push(("Mature", if mature { "Yes" } else { "No" }.to_string()));
+    }
+    let part_count = story
+        .num_parts
+        .or_else(|| story.parts.as_ref().map(|parts| parts.len() as u64));
+    if let Some(part_count) = part_count {
+        details.push(("Parts", part_count.to_string()));
+    }
+    for (label, count) in [
+        ("Reads", story.read_count),
+        ("Votes", story.vote_count),
+        ("Comments", story.comment_count),
+    ] {
+        if let Some(count) = count {
+            details.push((label, count.to_string()));
+        }
+    }
+    for (label, date) in [
+        ("Published", &story.create_date),
+        ("Updated", &story.modify_date),
+    ] {
+        if let Some(date) = date {
+            details.push((label, escape(date.as_str()).into_owned()));
+        }
+    }
+    let url = story_url(story_id);
+    details.push(("Source", format!(r#"<a href="{}">{}</a>"#, url, url)));
+    details.push(("Downloaded", escape(downloaded_at).into_owned()));
+    details
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_the_cover_path() {
+        let story: StoryResponse = serde_json::from_str(r#"{"title": "Title"}"#).unwrap();
+        let body = title_page_body(1, &story, "Title", Some(r#"images/a"b&c.jpg"#), "now");
+        assert!(body.contains(r#"<img src="images/a&quot;b&amp;c.jpg""#));
+    }
+}
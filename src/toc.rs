@@ -0,0 +1,187 @@
+use crate::html;
+use iepub::prelude::{EpubBook, EpubNav};
+use quick_xml::escape::escape;
+use std::io::{Read, Seek, Write};
+use zip::result::ZipResult;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// The file name of the table of contents page.
+pub(crate) const TOC_PAGE_FILE_NAME: &str = "toc.xhtml";
+
+/// The navigation document iepub writes, and the fallback target of the `toc` landmark.
+const NAV_FILE_NAME: &str = "nav.xhtml";
+
+/// An entry of the table of contents page.
+pub(crate) struct TocEntry {
+    title: String,
+    file_name: String,
+    word_count: Option<usize>,
+    children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    /// The entry of a chapter, linking to its file and counting the words of its `html`
+    /// when asked to.
+    pub(crate) fn chapter(title: String, file_name: &str, html: &str, word_counts: bool) -> Self {
+        TocEntry {
+            title,
+            file_name: file_name.to_string(),
+            word_count: word_counts.then(|| html::count_words(html)),
+            children: Vec::new(),
+        }
+    }
+
+    /// The entry of a story of an omnibus, linking to its title page and listing its
+    /// chapters. Its word count is the total of the chapters.
+    pub(crate) fn story(title: &str, file_name: &str, children: Vec<TocEntry>) -> Self {
+        TocEntry {
+            title: title.to_string(),
+            file_name: file_name.to_string(),
+            word_count: children.iter().map(|child| child.word_count).sum(),
+            children,
+        }
+    }
+}
+
+/// The body of the page listing the entries, in reading order.
+pub(crate) fn toc_page_body(entries: &[TocEntry]) -> String {
+    format!(
+        r#"<section epub:type="toc"><h1 style="text-align: center">Contents</h1>{}</section>"#,
+        toc_list(entries)
+    )
+}
+
+fn toc_list(entries: &[TocEntry]) -> String {
+    // Titled like the navigation, which numbers the chapters itself.
+    let mut list = String::from(r#"<ol style="list-style-type: none">"#);
+    for entry in entries {
+        list.push_str(&format!(
+            r#"<li><a href="{}">{}</a>"#,
+            escape(&entry.file_name),
+            escape(&entry.title)
+        ));
+        if let Some(word_count) = entry.word_count {
+            let unit = if word_count == 1 { "word" } else { "words" };
+            list.push_str(&format!(" <small>({} {})</small>", word_count, unit));
+        }
+        if !entry.children.is_empty() {
+            list.push_str(&toc_list(&entry.children));
+        }
+        list.push_str("</li>");
+    }
+    list.push_str("</ol>");
+    list
+}
+
+/// The body of the navigation document of a written book: its table of contents,
+/// followed by its landmarks.
+///
+/// Replaces iepub's own body, which links every entry with children to its first child
+/// rather than to the entry's own file.
+pub(crate) fn navigation_body(book: &EpubBook) -> String {
+    format!(
+        r#"<nav epub:type="toc" id="toc" role="doc-toc"><h1>{}</h1>{}</nav>{}"#,
+        escape(book.title()),
+        nav_list(book.nav()),
+        landmarks_nav(book)
+    )
+}
+
+fn nav_list<'a>(entries: impl Iterator<Item = &'a EpubNav>) -> String {
+    let mut list = String::from("<ol>");
+    for entry in entries {
+        list.push_str(&format!(
+            r#"<li><a href="{}">{}</a>"#,
+            escape(entry.file_name()),
+            escape(entry.title())
+        ));
+        if entry.child().len() > 0 {
+            list.push_str(&nav_list(entry.child()));
+        }
+        list.push_str("</li>");
+    }
+    list.push_str("</ol>");
+    list
+}
+
+/// The EPUB 3 landmarks of a written book: its cover page, table of contents and first
+/// chapter, as far as the book has them.
+///
+/// The table of contents is the page from [`toc_page_body`] when present, the navigation
+/// document otherwise. The first chapter is found through the `epub:type` of its section.
+fn landmarks_nav(book: &EpubBook) -> String {
+    let toc_file = if book
+        .chapters()
+        .any(|chapter| chapter.file_name() == TOC_PAGE_FILE_NAME)
+    {
+        TOC_PAGE_FILE_NAME
+    } else {
+        NAV_FILE_NAME
+    };
+    let first_chapter = book.chapters().find(|chapter| {
+        chapter
+            .data()
+            .is_some_and(|data| String::from_utf8_lossy(data).contains(r#"epub:type="chapter""#))
+    });
+
+    let mut landmarks = vec![("toc", toc_file, "Table of Contents")];
+    if let Some(cover) = book.cover_chapter() {
+        landmarks.insert(0, ("cover", cover.file_name(), "Cover"));
+    }
+    if let Some(chapter) = first_chapter {
+        landmarks.push(("bodymatter", chapter.file_name(), "Start of Content"));
+    }
+
+    let mut nav = String::from(r#"<nav epub:type="landmarks" hidden=""><ol>"#);
+    for (epub_type, file_name, label) in landmarks {
+        nav.push_str(&format!(
+            r#"<li><a epub:type="{}" href="{}">{}</a></li>"#,
+            epub_type,
+            escape(file_name),
+            label
+        ));
+    }
+    nav.push_str("</ol></nav>");
+    nav
+}
+
+/// Copies an EPUB written by iepub into `writer`, replacing the body of its navigation
+/// document with `body`, see [`navigation_body`]. Every other entry is copied as is,
+/// with the `mimetype` moved to the front where readers expect it.
+pub(crate) fn copy_with_navigation<R: Read + Seek, W: Write + Seek>(
+    epub: R,
+    body: &str,
+    writer: &mut W,
+) -> ZipResult<()> {
+    let mut archive = ZipArchive::new(epub)?;
+    let mut output = ZipWriter::new(writer);
+
+    let nav_path = format!("OEBPS/{}", NAV_FILE_NAME);
+    let mut indices: Vec<usize> = (0..archive.len()).collect();
+    indices.sort_by_key(|&i| archive.name_for_index(i) != Some("mimetype"));
+    for i in indices {
+        let file = archive.by_index_raw(i)?;
+        if file.name() != nav_path {
+            output.raw_copy_file(file)?;
+            continue;
+        }
+        let name = file.name().to_string();
+        drop(file);
+
+        let mut nav = String::new();
+        archive.by_index(i)?.read_to_string(&mut nav)?;
+        let start = nav.find("<body>").map(|start| start + "<body>".len());
+        let nav = match (start, nav.rfind("</body>")) {
+            (Some(start), Some(end)) if start <= end => {
+                format!("{}{}{}", &nav[..start], body, &nav[end..])
+            }
+            _ => nav,
+        };
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        output.start_file(name, options)?;
+        output.write_all(nav.as_bytes())?;
+    }
+    output.finish()?;
+    Ok(())
+}
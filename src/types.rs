@@ -1,9 +1,12 @@
+use crate::report::DownloadReport;
 pub use wp_mini::types::StoryResponse;
 pub struct StoryDownload<T> {
-    /// Sanitized Title ( Follow {id}-{title} )
+    /// Sanitized Title ( Follow the [`crate::FileNameTemplate`], `{id}-{title}` by default )
     pub sanitized_title: String,
-    /// The generated EPUB file, either as a PathBuf or Vec<u8>.
+    /// The generated EPUB file, either as a PathBuf, a Vec<u8> or the writer it was streamed into.
     pub epub_response: T,
     /// The full story metadata fetched from Wattpad.
     pub metadata: StoryResponse,
+    /// What made it into the EPUB, and what was skipped or replaced.
+    pub report: DownloadReport,
 }
@@ -0,0 +1,672 @@
+use crate::error::AppError;
+use crate::lang_util;
+use crate::models::{
+    ChapterJob, OMNIBUS_META_NAME, PartRecord, PreparedStory, ProcessedChapter, STORY_META_NAME,
+    VOLUME_META_NAME,
+};
+use crate::options::DownloadOptions;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use crate::processor::write_epub_file;
+use crate::processor::{
+    add_front_matter, add_processed_chapter, chapter_html, ensure_not_cancelled, epub_title,
+    fetch_cover, fetch_story_metadata, finish_book, load_parts_content, new_epub_builder,
+    process_chapters, record_missing_chapter, sanitized_title, select_parts, story_language_id,
+    write_epub,
+};
+use crate::progress::ProgressEvent;
+use crate::report::{CoverStatus, DownloadReport};
+use crate::style::add_stylesheet;
+use crate::toc::TocEntry;
+use crate::types::StoryDownload;
+use iepub::prelude::{EpubBook, EpubNav, read_from_vec};
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+#[cfg(not(target_arch = "wasm32"))] // Excluded for wasm32
+use std::path::{Path, PathBuf};
+use tracing::{info, instrument};
+use wp_mini::WattpadClient;
+use wp_mini::types::PartStubResponse;
+
+/// Updates an EPUB previously generated by this crate, rewriting it in place.
+///
+/// Only parts that are new or were modified since the EPUB was generated are
+/// downloaded and processed; unchanged chapters and their images are carried over.
+/// A [`crate::PartSelection`] in `options` applies as well, dropping unselected chapters.
+/// Omnibus EPUBs and volumes of split stories cannot be updated and fail with
+/// [`AppError::EpubIsOmnibus`] and [`AppError::EpubIsVolume`].
+///
+/// Excluded for wasm32
+///
+/// # Arguments
+/// * `options` - The download settings, see [`DownloadOptions`].
+/// * `epub_file` - The existing `.epub` file, replaced once the update succeeded.
+///
+/// # Returns
+/// A `Result` containing the `PathBuf` of the updated file.
+#[cfg(not(target_arch = "wasm32"))]
+#[instrument(skip(reqwest_client, wattpad_client, options), fields(id = story_id, path = %epub_file.display()))]
+pub async fn update_story_epub(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_id: u64,
+    options: &DownloadOptions,
+    epub_file: &Path,
+) -> Result<StoryDownload<PathBuf>, AppError> {
+    let existing_epub = std::fs::read(epub_file)?;
+    let prepared = options
+        .cancellation
+        .run_until_cancelled(prepare_updated_book(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            options,
+            existing_epub,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
+
+    write_epub_file(prepared.book, story_id, epub_file, options)?;
+
+    options.progress.emit(ProgressEvent::EpubSerialized {
+        bytes: std::fs::metadata(epub_file).ok().map(|m| m.len()),
+    });
+
+    info!(path = %epub_file.display(), "Successfully updated EPUB file");
+    Ok(StoryDownload {
+        sanitized_title: prepared.sanitized_title,
+        epub_response: epub_file.to_path_buf(),
+        metadata: prepared.metadata,
+        report: prepared.report,
+    })
+}
+
+/// Updates an EPUB previously generated by this crate, returning the new EPUB as bytes.
+///
+/// Only parts that are new or were modified since the EPUB was generated are
+/// downloaded and processed; unchanged chapters and their images are carried over.
+/// A [`crate::PartSelection`] in `options` applies as well, dropping unselected chapters.
+/// Omnibus EPUBs and volumes of split stories cannot be updated and fail with
+/// [`AppError::EpubIsOmnibus`] and [`AppError::EpubIsVolume`].
+///
+/// # Arguments
+/// * `options` - The download settings, see [`DownloadOptions`].
+/// * `existing_epub` - The bytes of the existing EPUB.
+///
+/// # Returns
+/// A `Result` containing the `Vec<u8>` of the updated EPUB file.
+#[instrument(skip(reqwest_client, wattpad_client, options, existing_epub), fields(id = story_id))]
+pub async fn update_story_epub_in_memory(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_id: u64,
+    options: &DownloadOptions,
+    existing_epub: Vec<u8>,
+) -> Result<StoryDownload<Vec<u8>>, AppError> {
+    let mut prepared = options
+        .cancellation
+        .run_until_cancelled(prepare_updated_book(
+            wattpad_client,
+            reqwest_client,
+            story_id,
+            options,
+            existing_epub,
+        ))
+        .await
+        .ok_or(AppError::Cancelled)??;
+
+    let mut cursor = Cursor::new(Vec::new());
+    write_epub(&mut prepared.book, story_id, &mut cursor)?;
+    ensure_not_cancelled(options)?;
+    let epub_bytes = cursor.into_inner();
+
+    options.progress.emit(ProgressEvent::EpubSerialized {
+        bytes: Some(epub_bytes.len() as u64),
+    });
+
+    info!(
+        bytes = epub_bytes.len(),
+        "Successfully updated EPUB in memory"
+    );
+    Ok(StoryDownload {
+        sanitized_title: prepared.sanitized_title,
+        epub_response: epub_bytes,
+        metadata: prepared.metadata,
+        report: prepared.report,
+    })
+}
+
+/// What happens to a part of the story during an update.
+#[derive(Debug, PartialEq)]
+enum PartPlan {
+    /// The existing chapter file is unchanged and carried over.
+    Keep { file_name: String },
+    /// The part is new or modified and gets processed into `{file_stem}.xhtml`.
+    Process { file_stem: String },
+}
+
+async fn prepare_updated_book(
+    wattpad_client: &WattpadClient,
+    reqwest_client: &Client,
+    story_id: u64,
+    options: &DownloadOptions,
+    existing_epub: Vec<u8>,
+) -> Result<PreparedStory, AppError> {
+    info!("Starting story update");
+
+    // --- 1. Read the Existing EPUB ---
+    let mut existing =
+        read_from_vec(existing_epub).map_err(|source| AppError::EpubReadFailed { source })?;
+    let stored_meta = |name: &str| {
+        existing
+            .meta()
+            .iter()
+            .find(|meta| meta.get_attr("name").is_some_and(|found| found == name))
+            .and_then(|meta| meta.get_attr("content"))
+    };
+    // An omnibus holds part records of several stories; rewriting it would drop the others.
+    if stored_meta(OMNIBUS_META_NAME).is_some() {
+        return Err(AppError::EpubIsOmnibus);
+    }
+    // A volume holds a slice of the story; rewriting it would turn it into the whole story.
+    if let Some(volume) = stored_meta(VOLUME_META_NAME) {
+        return Err(AppError::EpubIsVolume {
+            volume: volume.parse().unwrap_or_default(),
+        });
+    }
+    let stored_story_id =
+        stored_meta(STORY_META_NAME).and_then(|content| content.parse::<u64>().ok());
+    if let Some(found) = stored_story_id.filter(|&found| found != story_id) {
+        return Err(AppError::EpubStoryMismatch { story_id, found });
+    }
+    let part_records: Vec<PartRecord> = existing
+        .meta()
+        .iter()
+        .filter_map(PartRecord::from_meta)
+        .collect();
+
+    // --- 2. Fetch Story Info ---
+    let story = fetch_story_metadata(wattpad_client, story_id, options).await?;
+    let (positions, chapter_metadata): (Vec<usize>, Vec<PartStubResponse>) =
+        select_parts(&story, story_id, options)?.into_iter().unzip();
+    let total_chapter_count = chapter_metadata.len();
+
+    // --- 3. Work Out Which Parts Changed ---
+    let mut existing_chapters = read_chapter_bodies(&mut existing);
+    let mut plans: Vec<PartPlan> = chapter_metadata
+        .iter()
+        .map(|part| plan_part(part, &part_records, &existing_chapters))
+        .collect();
+    allocate_file_stems(&mut plans, &positions, &part_records);
+
+    let kept_files: HashSet<&str> = plans
+        .iter()
+        .filter_map(|plan| match plan {
+            PartPlan::Keep { file_name } => Some(file_name.as_str()),
+            PartPlan::Process { .. } => None,
+        })
+        .collect();
+    info!(
+        unchanged = kept_files.len(),
+        changed = total_chapter_count - kept_files.len(),
+        "Compared the existing EPUB with the story"
+    );
+
+    // --- 4. Fetch Content of the Changed Parts ---
+    let changed_parts: Vec<_> = chapter_metadata
+        .iter()
+        .zip(&plans)
+        .filter(|(_, plan)| matches!(plan, PartPlan::Process { .. }))
+        .map(|(part, _)| part)
+        .collect();
+    let mut chapter_html_map =
+        load_parts_content(wattpad_client, &story, story_id, &changed_parts, options).await?;
+
+    // --- 5. Process the Changed Parts ---
+    let mut report = DownloadReport {
+        total_chapters: total_chapter_count,
+        ..Default::default()
+    };
+    let language_code = lang_util::get_lang_code(story_language_id(&story));
+    let mut chapters_to_process = Vec::new();
+    let mut kept_chapters = Vec::new();
+    for (i, (part, plan)) in chapter_metadata.into_iter().zip(plans).enumerate() {
+        let index = i + 1;
+        match plan {
+            PartPlan::Keep { file_name } => {
+                options.progress.emit(ProgressEvent::ChapterReused {
+                    index,
+                    total: total_chapter_count,
+                });
+                kept_chapters.push(KeptChapter {
+                    index,
+                    position: positions[i],
+                    title: part.title.clone().unwrap_or_default(),
+                    body: existing_chapters.remove(&file_name).unwrap_or_default(),
+                    record: PartRecord {
+                        part_id: part.id.unwrap_or_default(),
+                        content_hash: part_records
+                            .iter()
+                            .find(|record| record.file_name == file_name)
+                            .and_then(|record| record.content_hash.clone()),
+                        file_name,
+                        modify_date: part.modify_date,
+                    },
+                });
+            }
+            PartPlan::Process { file_stem } => {
+                let part_id = part.id.unwrap_or_default() as i64;
+                match chapter_html_map.remove(&part_id) {
+                    Some(html) => {
+                        chapters_to_process.push(ChapterJob::new(
+                            index,
+                            positions[i],
+                            file_stem,
+                            part,
+                            html,
+                        ));
+                    }
+                    None => record_missing_chapter(
+                        story_id,
+                        part,
+                        total_chapter_count,
+                        options,
+                        &mut report,
+                    )?,
+                }
+            }
+        }
+    }
+
+    let processed = process_chapters(
+        reqwest_client,
+        chapters_to_process,
+        total_chapter_count,
+        options,
+        &mut report,
+    )
+    .await?;
+
+    if processed.is_empty() && kept_chapters.is_empty() {
+        return Err(AppError::NoChaptersProcessed {
+            story_id,
+            failed: report.failed_chapters.len() + report.missing_chapters.len(),
+        });
+    }
+
+    report.reused_chapters = kept_chapters.len();
+    report.included_chapters = kept_chapters.len() + processed.len();
+    info!(
+        reused = kept_chapters.len(),
+        processed = processed.len(),
+        total_count = total_chapter_count,
+        "Finished chapter processing"
+    );
+
+    // --- 6. Build EPUB ---
+    let title = epub_title(&story, &positions);
+    let mut epub_builder = new_epub_builder(&story, &title).custome_nav(true);
+    let existing_cover = existing.cover_mut().and_then(|cover| {
+        let file_name = cover.file_name().to_string();
+        cover.data_mut().map(|data| (file_name, data.to_vec()))
+    });
+    let cover_path = match existing_cover {
+        Some((file_name, data)) => {
+            epub_builder = epub_builder.cover(file_name.as_str(), data);
+            report.cover = CoverStatus::Embedded;
+            Some(file_name)
+        }
+        None => match fetch_cover(reqwest_client, &story, story_id, options, &mut report).await? {
+            Some(cover_data) => {
+                epub_builder = epub_builder.cover("cover.jpg", cover_data);
+                Some("cover.jpg".to_string())
+            }
+            None => None,
+        },
+    };
+
+    // Carry over the images of unchanged chapters.
+    let kept_image_dirs: Vec<String> = kept_chapters
+        .iter()
+        .map(|kept| format!("images/chapter_{}/", file_stem(&kept.record.file_name)))
+        .collect();
+    for asset in existing.assets_mut() {
+        let file_name = asset.file_name().to_string();
+        if !kept_image_dirs.iter().any(|dir| file_name.starts_with(dir)) {
+            continue;
+        }
+        if let Some(data) = asset.data_mut() {
+            epub_builder = epub_builder.add_assets(file_name, data.to_vec());
+        }
+    }
+
+    // Merge both kinds of chapters back into story order.
+    let mut chapters = Vec::with_capacity(report.included_chapters);
+    let mut kept_chapters = kept_chapters.into_iter().peekable();
+    for chapter in processed {
+        while let Some(kept) = kept_chapters.next_if(|kept| kept.index < chapter.index) {
+            chapters.push(BookChapter::Kept(kept));
+        }
+        chapters.push(BookChapter::Processed(chapter));
+    }
+    chapters.extend(kept_chapters.map(BookChapter::Kept));
+
+    let toc_titles: Vec<String> = chapters
+        .iter()
+        .map(|chapter| {
+            let heading = &options.chapter_heading;
+            heading.toc_title(chapter.position(), chapter.title())
+        })
+        .collect();
+    let toc_entries: Vec<TocEntry> = chapters
+        .iter()
+        .zip(&toc_titles)
+        .map(|(chapter, toc_title)| {
+            TocEntry::chapter(
+                toc_title.clone(),
+                chapter.file_name(),
+                chapter.html(),
+                options.toc_word_counts,
+            )
+        })
+        .collect();
+    epub_builder = add_front_matter(
+        epub_builder,
+        story_id,
+        &story,
+        &title,
+        cover_path.as_deref(),
+        &toc_entries,
+        options,
+    );
+
+    let mut part_records = Vec::with_capacity(chapters.len());
+    for (chapter, toc_title) in chapters.into_iter().zip(toc_titles) {
+        epub_builder = epub_builder.add_nav(
+            EpubNav::default()
+                .with_title(toc_title)
+                .with_file_name(chapter.file_name()),
+        );
+        match chapter {
+            BookChapter::Kept(kept) => {
+                epub_builder = epub_builder.add_chapter(chapter_html(
+                    &kept.title,
+                    &kept.record.file_name,
+                    language_code,
+                    kept.body,
+                ));
+                part_records.push(kept.record);
+            }
+            BookChapter::Processed(chapter) => {
+                part_records.push(chapter.part_record());
+                epub_builder = add_processed_chapter(
+                    epub_builder,
+                    story_id,
+                    chapter,
+                    language_code,
+                    &mut report,
+                );
+            }
+        }
+    }
+
+    let mut book = finish_book(epub_builder, story_id, &part_records)?;
+    add_stylesheet(&mut book, options);
+    Ok(PreparedStory {
+        book,
+        sanitized_title: sanitized_title(story_id, &story, &title, options),
+        metadata: story,
+        report,
+    })
+}
+
+/// An unchanged chapter carried over from the existing EPUB.
+struct KeptChapter {
+    index: usize,
+    /// The 1-based position of the part in the story.
+    position: usize,
+    title: String,
+    record: PartRecord,
+    body: String,
+}
+
+/// A chapter of the updated EPUB, either carried over or freshly processed.
+enum BookChapter {
+    Kept(KeptChapter),
+    Processed(ProcessedChapter),
+}
+
+impl BookChapter {
+    fn position(&self) -> usize {
+        match self {
+            BookChapter::Kept(kept) => kept.position,
+            BookChapter::Processed(chapter) => chapter.position,
+        }
+    }
+
+    fn title(&self) -> &str {
+        match self {
+            BookChapter::Kept(kept) => &kept.title,
+            BookChapter::Processed(chapter) => &chapter.title,
+        }
+    }
+
+    fn file_name(&self) -> &str {
+        match self {
+            BookChapter::Kept(kept) => &kept.record.file_name,
+            BookChapter::Processed(chapter) => &chapter.file_name,
+        }
+    }
+
+    fn html(&self) -> &str {
+        match self {
+            BookChapter::Kept(kept) => &kept.body,
+            BookChapter::Processed(chapter) => &chapter.html_content,
+        }
+    }
+}
+
+/// Reads the body of every chapter in the existing EPUB, keyed by file name.
+fn read_chapter_bodies(book: &mut EpubBook) -> HashMap<String, String> {
+    book.chapters_mut()
+        .filter_map(|chapter| {
+            let file_name = chapter.file_name().to_string();
+            let body = String::from_utf8(chapter.data_mut()?.to_vec()).ok()?;
+            Some((file_name, body.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Decides whether a part can be carried over from the existing EPUB.
+///
+/// EPUBs generated before part records were stored are matched by chapter title.
+fn plan_part(
+    part: &PartStubResponse,
+    part_records: &[PartRecord],
+    existing_chapters: &HashMap<String, String>,
+) -> PartPlan {
+    let keep = |file_name: &str| match existing_chapters.contains_key(file_name) {
+        true => PartPlan::Keep {
+            file_name: file_name.to_string(),
+        },
+        false => PartPlan::Process {
+            file_stem: file_stem(file_name).to_string(),
+        },
+    };
+
+    if part_records.is_empty() {
+        let title = part.title.as_deref().unwrap_or_default();
+        let heading = format!(">{}</h1>", quick_xml::escape::escape(title));
+        return existing_chapters
+            .iter()
+            .find(|(_, body)| body.contains(&heading))
+            .map_or(
+                PartPlan::Process {
+                    file_stem: String::new(),
+                },
+                |(file_name, _)| keep(file_name),
+            );
+    }
+
+    match part_records
+        .iter()
+        .find(|record| Some(record.part_id) == part.id)
+    {
+        Some(record) if record.modify_date.is_some() && record.modify_date == part.modify_date => {
+            keep(&record.file_name)
+        }
+        // Modified parts are processed into their old file.
+        Some(record) => PartPlan::Process {
+            file_stem: file_stem(&record.file_name).to_string(),
+        },
+        None => PartPlan::Process {
+            file_stem: String::new(),
+        },
+    }
+}
+
+/// Gives every new part a file stem no other chapter of the EPUB uses.
+fn allocate_file_stems(plans: &mut [PartPlan], positions: &[usize], part_records: &[PartRecord]) {
+    let mut used: HashSet<String> = plans
+        .iter()
+        .map(|plan| match plan {
+            PartPlan::Keep { file_name } => file_stem(file_name).to_string(),
+            PartPlan::Process { file_stem } => file_stem.clone(),
+        })
+        .chain(
+            part_records
+                .iter()
+                .map(|record| file_stem(&record.file_name).to_string()),
+        )
+        .collect();
+
+    let mut next = 1;
+    for (plan, position) in plans.iter_mut().zip(positions) {
+        if let PartPlan::Process { file_stem } = plan
+            && file_stem.is_empty()
+        {
+            // Prefer the part's position in the story, as in a fresh download.
+            let mut candidate = position.to_string();
+            while used.contains(&candidate) {
+                candidate = next.to_string();
+                next += 1;
+            }
+            used.insert(candidate.clone());
+            *file_stem = candidate;
+        }
+    }
+}
+
+fn file_stem(file_name: &str) -> &str {
+    file_name.strip_suffix(".xhtml").unwrap_or(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(id: u64, title: &str, modify_date: &str) -> PartStubResponse {
+        serde_json::from_str(&format!(
+            r#"{{"id": {}, "title": "{}", "modifyDate": "{}"}}"#,
+            id, title, modify_date
+        ))
+        .unwrap()
+    }
+
+    fn record(part_id: u64, file_name: &str, modify_date: &str) -> PartRecord {
+        PartRecord {
+            part_id,
+            file_name: file_name.to_string(),
+            modify_date: Some(modify_date.to_string()),
+            content_hash: None,
+        }
+    }
+
+    fn chapters(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(file_name, body)| (file_name.to_string(), body.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_unchanged_parts_and_reprocesses_modified_ones() {
+        let records = [record(7, "3.xhtml", "2024-01-01")];
+        let existing = chapters(&[("3.xhtml", "<h1>Chapter</h1>")]);
+
+        assert_eq!(
+            plan_part(&part(7, "Chapter", "2024-01-01"), &records, &existing),
+            PartPlan::Keep {
+                file_name: "3.xhtml".to_string()
+            }
+        );
+        assert_eq!(
+            plan_part(&part(7, "Chapter", "2024-02-01"), &records, &existing),
+            PartPlan::Process {
+                file_stem: "3".to_string()
+            }
+        );
+        assert_eq!(
+            plan_part(&part(8, "Chapter", "2024-01-01"), &records, &existing),
+            PartPlan::Process {
+                file_stem: String::new()
+            }
+        );
+    }
+
+    #[test]
+    fn matches_legacy_chapters_by_heading() {
+        let existing = chapters(&[
+            ("1.xhtml", "<h1>Fish &amp; Chips</h1><p>Text</p>"),
+            ("2.xhtml", "<h1>Dessert</h1><p>Text</p>"),
+        ]);
+
+        assert_eq!(
+            plan_part(&part(1, "Fish & Chips", "2024"), &[], &existing),
+            PartPlan::Keep {
+                file_name: "1.xhtml".to_string()
+            }
+        );
+        assert_eq!(
+            plan_part(&part(3, "Coffee", "2024"), &[], &existing),
+            PartPlan::Process {
+                file_stem: String::new()
+            }
+        );
+    }
+
+    #[test]
+    fn gives_new_parts_unused_file_stems() {
+        // Part 2 was removed and a new part takes its position, while the existing
+        // chapter at position 3 still uses the file `2.xhtml`.
+        let records = [record(10, "1.xhtml", "2024"), record(30, "2.xhtml", "2024")];
+        let mut plans = vec![
+            PartPlan::Keep {
+                file_name: "1.xhtml".to_string(),
+            },
+            PartPlan::Process {
+                file_stem: String::new(),
+            },
+            PartPlan::Keep {
+                file_name: "2.xhtml".to_string(),
+            },
+            PartPlan::Process {
+                file_stem: String::new(),
+            },
+        ];
+        allocate_file_stems(&mut plans, &[1, 2, 3, 4], &records);
+
+        assert_eq!(
+            plans[1],
+            PartPlan::Process {
+                file_stem: "3".to_string()
+            }
+        );
+        assert_eq!(
+            plans[3],
+            PartPlan::Process {
+                file_stem: "4".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use crate::html;
+use crate::models::ProcessedChapter;
+
+/// Splits a long story into several volume EPUBs.
+///
+/// Chapters are never split: a new volume starts before the chapter that would push
+/// the current volume over the limit, and a single chapter above the limit gets a
+/// volume of its own. Only [`crate::download_story_to_folder`] splits stories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeSplit {
+    /// At most this many chapters per volume.
+    Chapters(usize),
+    /// At most this many words per volume, counted like [`crate::PlannedPart::word_count`].
+    Words(usize),
+    /// At most this many bytes per volume, estimated from the size of the chapter HTML
+    /// and images before compression.
+    Bytes(u64),
+}
+
+impl VolumeSplit {
+    fn limit(self) -> u64 {
+        match self {
+            VolumeSplit::Chapters(chapters) => chapters as u64,
+            VolumeSplit::Words(words) => words as u64,
+            VolumeSplit::Bytes(bytes) => bytes,
+        }
+        .max(1)
+    }
+
+    fn measure(self, chapter: &ProcessedChapter) -> u64 {
+        match self {
+            VolumeSplit::Chapters(_) => 1,
+            VolumeSplit::Words(_) => html::count_words(&chapter.html_content) as u64,
+            VolumeSplit::Bytes(_) => {
+                let images: usize = chapter.images.iter().map(|image| image.data.len()).sum();
+                (chapter.html_content.len() + images) as u64
+            }
+        }
+    }
+
+    /// Groups chapters, in order, into volumes.
+    pub(crate) fn split(self, chapters: Vec<ProcessedChapter>) -> Vec<Vec<ProcessedChapter>> {
+        let limit = self.limit();
+        let mut volumes: Vec<Vec<ProcessedChapter>> = Vec::new();
+        let mut current = Vec::new();
+        let mut current_size = 0;
+        for chapter in chapters {
+            let size = self.measure(&chapter);
+            if !current.is_empty() && current_size + size > limit {
+                volumes.push(std::mem::take(&mut current));
+                current_size = 0;
+            }
+            current_size += size;
+            current.push(chapter);
+        }
+        if !current.is_empty() {
+            volumes.push(current);
+        }
+        volumes
+    }
+}
+
+/// The title of the `number`-th (1-based) volume of a story.
+pub(crate) fn volume_title(title: &str, number: usize) -> String {
+    format!("{} Vol. {}", title, number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageAsset;
+
+    /// A chapter of `words` one-letter words and an image of `image_bytes` bytes.
+    fn chapter(index: usize, words: usize, image_bytes: usize) -> ProcessedChapter {
+        ProcessedChapter {
+            index,
+            position: index,
+            part_id: index as u64,
+            modify_date: None,
+            content_hash: String::new(),
+            title: format!("Chapter {}", index),
+            file_name: format!("{}.xhtml", index),
+            html_content: vec!["a"; words].join(" "),
+            images: vec![ImageAsset {
+                epub_path: format!("images/chapter_{}/0.jpg", index),
+                data: vec![0; image_bytes],
+            }],
+            image_count: 1,
+            failed_images: Vec::new(),
+        }
+    }
+
+    /// The chapter indices of every volume.
+    fn split(split: VolumeSplit, chapters: Vec<ProcessedChapter>) -> Vec<Vec<usize>> {
+        split
+            .split(chapters)
+            .into_iter()
+            .map(|volume| volume.iter().map(|chapter| chapter.index).collect())
+            .collect()
+    }
+
+    #[test]
+    fn fills_volumes_up_to_exactly_the_limit() {
+        let chapters = || (1..=5).map(|index| chapter(index, 10, 0)).collect();
+        assert_eq!(
+            split(VolumeSplit::Chapters(2), chapters()),
+            [vec![1, 2], vec![3, 4], vec![5]]
+        );
+        assert_eq!(
+            split(VolumeSplit::Words(20), chapters()),
+            [vec![1, 2], vec![3, 4], vec![5]]
+        );
+        // Each chapter takes 19 bytes of HTML and a 1-byte image.
+        assert_eq!(
+            split(
+                VolumeSplit::Bytes(40),
+                (1..=3).map(|i| chapter(i, 10, 1)).collect()
+            ),
+            [vec![1, 2], vec![3]]
+        );
+    }
+
+    #[test]
+    fn gives_an_oversized_chapter_a_volume_of_its_own() {
+        let chapters = vec![chapter(1, 5, 0), chapter(2, 50, 0), chapter(3, 5, 0)];
+        assert_eq!(
+            split(VolumeSplit::Words(10), chapters),
+            [vec![1], vec![2], vec![3]]
+        );
+        assert_eq!(
+            split(VolumeSplit::Bytes(10), vec![chapter(1, 1, 100)]),
+            [vec![1]]
+        );
+    }
+
+    #[test]
+    fn splits_zero_or_one_chapters() {
+        assert!(split(VolumeSplit::Chapters(1), Vec::new()).is_empty());
+        assert_eq!(
+            split(VolumeSplit::Chapters(0), vec![chapter(1, 1, 0)]),
+            [vec![1]]
+        );
+        assert_eq!(
+            split(VolumeSplit::Words(1), vec![chapter(1, 0, 0)]),
+            [vec![1]]
+        );
+    }
+}